@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+use pulldown_cmark::{Event, Tag};
+
+use super::escape_html;
+
+// The type and title of `[!WARNING] Optional title` or `:::warning Optional title`
+fn parse_marker(marker: &str) -> Option<(String, String)> {
+    let marker = marker.trim();
+    let (kind, title) = {
+        if marker.starts_with("[!") {
+            let end = match marker.find(']') {
+                Some(end) => end,
+                None      => return None,
+            };
+            (&marker[2..end], marker[end + 1..].trim())
+        }
+        else if marker.starts_with(":::") {
+            let rest = marker[3..].trim();
+            let mut split = rest.splitn(2, char::is_whitespace);
+            (split.next().unwrap_or(""), split.next().unwrap_or("").trim())
+        }
+        else {
+            return None;
+        }
+    };
+
+    if kind.is_empty() || !kind.chars().all(|c| c.is_alphanumeric() || c == '-') {
+        return None;
+    }
+
+    let kind  = kind.to_lowercase();
+    let title = {
+        if title.is_empty() {
+            let mut chars = kind.chars();
+            let first     = chars.next().unwrap();
+            first.to_uppercase().chain(chars).collect()
+        }
+        else {
+            title.to_owned()
+        }
+    };
+    Some((kind, title))
+}
+
+fn open_aside<'a>(kind: &str, title: &str) -> Event<'a> {
+    Event::Html(Cow::Owned(format!("<aside class=\"admonition {}\">\n<p class=\"admonition-title\">{}</p>\n",
+                                   escape_html(kind), escape_html(title))))
+}
+
+fn close_aside<'a>() -> Event<'a> {
+    Event::Html(Cow::Borrowed("</aside>\n"))
+}
+
+// `> [!NOTE]` blockquotes: the marker must open the blockquote's first paragraph
+fn blockquotes<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out    : Vec<Event<'a>> = Vec::with_capacity(events.len());
+    let mut quotes : Vec<bool> = vec![];
+    let mut events = events.into_iter().peekable();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::BlockQuote) => {
+                let mut lookahead = vec![];
+                if let Some(Event::Start(Tag::Paragraph)) = events.peek().cloned() {
+                    lookahead.push(events.next().unwrap());
+                    if let Some(Event::Text(text)) = events.peek().cloned() {
+                        let first_line = text.lines().next().unwrap_or("");
+                        let marker     = if first_line.starts_with("[!") { parse_marker(first_line) } else { None };
+                        if let Some((kind, title)) = marker {
+                            events.next();
+                            out.push(open_aside(&kind, &title));
+                            quotes.push(true);
+
+                            let rest = text[first_line.len()..].trim_left().to_owned();
+                            if !rest.is_empty() {
+                                out.push(Event::Start(Tag::Paragraph));
+                                out.push(Event::Text(Cow::Owned(rest)));
+                            }
+                            else {
+                                if let Some(&Event::SoftBreak) = events.peek() {
+                                    events.next();
+                                }
+                                match events.peek() {
+                                    Some(&Event::End(Tag::Paragraph)) => { events.next(); },
+                                    _ => out.push(Event::Start(Tag::Paragraph)),
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                }
+                quotes.push(false);
+                out.push(Event::Start(Tag::BlockQuote));
+                out.extend(lookahead);
+            },
+            Event::End(Tag::BlockQuote) => {
+                if quotes.pop().unwrap_or(false) {
+                    out.push(close_aside());
+                }
+                else {
+                    out.push(Event::End(Tag::BlockQuote));
+                }
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// `:::tip` ... `:::` containers, whose marker lines the parser leaves inside paragraphs
+fn containers<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out  : Vec<Event<'a>> = Vec::with_capacity(events.len());
+    let mut open = 0;
+    let mut para : Option<Vec<Event<'a>>> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Paragraph) if para.is_none() => para = Some(vec![]),
+            Event::End(Tag::Paragraph) if para.is_some() => {
+                // Split the paragraph into lines, turning marker lines into aside boundaries
+                let mut lines : Vec<Vec<Event<'a>>> = vec![vec![]];
+                for e in para.take().unwrap() {
+                    match e {
+                        Event::SoftBreak => lines.push(vec![]),
+                        other            => lines.last_mut().unwrap().push(other),
+                    }
+                }
+
+                let mut para_open = false;
+                for line in lines {
+                    let marker = match line.first() {
+                        Some(&Event::Text(ref text)) if line.len() == 1 && text.trim().starts_with(":::") => {
+                            Some(text.trim().to_owned())
+                        },
+                        _ => None,
+                    };
+
+                    match marker.as_ref().map(|m| (m.as_str(), parse_marker(m))) {
+                        Some((_, Some((kind, title)))) => {
+                            if para_open {
+                                out.push(Event::End(Tag::Paragraph));
+                                para_open = false;
+                            }
+                            out.push(open_aside(&kind, &title));
+                            open += 1;
+                        },
+                        Some((":::", None)) if open > 0 => {
+                            if para_open {
+                                out.push(Event::End(Tag::Paragraph));
+                                para_open = false;
+                            }
+                            out.push(close_aside());
+                            open -= 1;
+                        },
+                        _ => {
+                            if para_open {
+                                out.push(Event::SoftBreak);
+                            }
+                            else {
+                                out.push(Event::Start(Tag::Paragraph));
+                                para_open = true;
+                            }
+                            out.extend(line);
+                        },
+                    }
+                }
+                if para_open {
+                    out.push(Event::End(Tag::Paragraph));
+                }
+            },
+            other => {
+                match para {
+                    Some(ref mut buf) => buf.push(other),
+                    None              => out.push(other),
+                }
+            },
+        }
+    }
+
+    for _ in 0..open {
+        out.push(close_aside());
+    }
+    out
+}
+
+// Surrounds `:::` marker lines with blank lines so that the parser does not fold them into
+// a neighbouring paragraph or list item
+pub fn separate_containers(in_buf: &str) -> String {
+    let mut out      = String::with_capacity(in_buf.len());
+    let mut in_fence = false;
+
+    for line in in_buf.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && trimmed.starts_with(":::") {
+            out.push('\n');
+            out.push_str(trimmed);
+            out.push_str("\n\n");
+        }
+        else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+pub fn admonitions<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    containers(blockquotes(events))
+}
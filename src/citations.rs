@@ -0,0 +1,652 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use pulldown_cmark::{Event, Tag};
+
+use super::{QuiltError, escape_html};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    AuthorDate,
+    Numeric,
+}
+
+impl Style {
+    pub fn parse(name: &str) -> Result<Style, QuiltError> {
+        match name {
+            "author-date" => Ok(Style::AuthorDate),
+            "numeric"     => Ok(Style::Numeric),
+            _ => Err(QuiltError {source : "Citation".to_owned(),
+                                 message: format!("Unknown citation style {} (expected author-date or numeric)", name)}),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Name {
+    first : String,
+    last  : String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind    : String,
+    fields  : HashMap<String, String>,
+    authors : Vec<Name>,
+}
+
+impl Entry {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|f| f.as_str())
+    }
+
+    fn year(&self) -> &str {
+        self.field("year").unwrap_or("n.d.")
+    }
+}
+
+#[derive(Debug)]
+pub struct Bibliography {
+    pub path : PathBuf,
+    entries  : HashMap<String, Entry>,
+}
+
+fn bib_err(path: &Path, line: usize, message: String) -> QuiltError {
+    QuiltError {source : "Bibliography".to_owned(),
+                message: format!("{}:{}: {}", path.display(), line, message)}
+}
+
+struct BibParser<'b> {
+    chars   : Peekable<Chars<'b>>,
+    line    : usize,
+    path    : &'b Path,
+    strings : HashMap<String, String>,
+}
+
+impl<'b> BibParser<'b> {
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, want: char) -> Result<(), QuiltError> {
+        self.skip_space();
+        match self.next() {
+            Some(c) if c == want => Ok(()),
+            Some(c) => Err(bib_err(self.path, self.line, format!("expected '{}', found '{}'", want, c))),
+            None    => Err(bib_err(self.path, self.line, format!("expected '{}', found end of file", want))),
+        }
+    }
+
+    fn ident(&mut self) -> String {
+        self.skip_space();
+        let mut ident = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_alphanumeric() || "_-:./+".contains(c) {
+                ident.push(c);
+                self.next();
+            }
+            else {
+                break;
+            }
+        }
+        ident
+    }
+
+    // A field value: {...}, "...", a number or a @string name, joined with #
+    fn value(&mut self) -> Result<String, QuiltError> {
+        let mut value = String::new();
+        loop {
+            self.skip_space();
+            match self.chars.peek().cloned() {
+                Some('{') => {
+                    self.next();
+                    value.push_str(&self.group('}')?);
+                },
+                Some('"') => {
+                    self.next();
+                    value.push_str(&self.group('"')?);
+                },
+                Some(c) if c.is_alphanumeric() => {
+                    let name = self.ident();
+                    if name.chars().all(|c| c.is_ascii_digit()) {
+                        value.push_str(&name);
+                    }
+                    else {
+                        match self.strings.get(&name.to_lowercase()) {
+                            Some(s) => value.push_str(s),
+                            None => return Err(bib_err(self.path, self.line, format!("undefined string '{}'", name))),
+                        }
+                    }
+                },
+                _ => return Err(bib_err(self.path, self.line, "expected a field value".to_owned())),
+            }
+
+            self.skip_space();
+            if self.chars.peek() == Some(&'#') {
+                self.next();
+            }
+            else {
+                return Ok(value);
+            }
+        }
+    }
+
+    // Raw text up to `close` at brace depth zero, with inner braces kept for clean()
+    fn group(&mut self, close: char) -> Result<String, QuiltError> {
+        let start = self.line;
+        let mut depth = 0;
+        let mut text  = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == close && depth == 0 => return Ok(text),
+                Some('{') => {
+                    depth += 1;
+                    text.push('{');
+                },
+                Some('}') => {
+                    depth -= 1;
+                    text.push('}');
+                },
+                Some(c)   => text.push(c),
+                None      => return Err(bib_err(self.path, start, "unterminated field value".to_owned())),
+            }
+        }
+    }
+
+    fn entries(&mut self) -> Result<HashMap<String, Entry>, QuiltError> {
+        let mut entries = HashMap::new();
+
+        loop {
+            // Anything outside an @entry is a comment
+            loop {
+                match self.next() {
+                    Some('@') => break,
+                    Some(_)   => (),
+                    None      => return Ok(entries),
+                }
+            }
+
+            let kind = self.ident().to_lowercase();
+            let line = self.line;
+            self.skip_space();
+            let close = match self.next() {
+                Some('{') => '}',
+                Some('(') => ')',
+                _ => return Err(bib_err(self.path, line, format!("expected '{{' after @{}", kind))),
+            };
+
+            match kind.as_str() {
+                "comment" | "preamble" => {
+                    self.group(close)?;
+                },
+                "string" => {
+                    let name = self.ident().to_lowercase();
+                    self.expect('=')?;
+                    let value = self.value()?;
+                    self.strings.insert(name, value);
+                    self.expect(close)?;
+                },
+                _ => {
+                    let key = self.ident();
+                    if key.is_empty() {
+                        return Err(bib_err(self.path, line, format!("@{} entry has no key", kind)));
+                    }
+
+                    let mut fields = HashMap::new();
+                    loop {
+                        self.skip_space();
+                        match self.chars.peek().cloned() {
+                            Some(',') => { self.next(); },
+                            Some(c) if c == close => {
+                                self.next();
+                                break;
+                            },
+                            Some(_) => {
+                                let name = self.ident().to_lowercase();
+                                if name.is_empty() {
+                                    return Err(bib_err(self.path, self.line, format!("malformed field in {}", key)));
+                                }
+                                self.expect('=')?;
+                                fields.insert(name, self.value()?);
+                            },
+                            None => return Err(bib_err(self.path, line, format!("unterminated entry {}", key))),
+                        }
+                    }
+
+                    if entries.contains_key(&key) {
+                        return Err(bib_err(self.path, line, format!("duplicate entry {}", key)));
+                    }
+
+                    let authors = fields.get("author").or_else(|| fields.get("editor"))
+                                        .map(|a| names(a)).unwrap_or_default();
+                    let fields  = fields.into_iter().map(|(k, v)| (k, clean(&v))).collect();
+                    entries.insert(key, Entry {kind: kind, fields: fields, authors: authors});
+                },
+            }
+        }
+    }
+}
+
+// Turns the TeX in a field into plain text: braces dropped, accents and dashes converted
+fn clean(value: &str) -> String {
+    let mut out   = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => (),
+            '~' => out.push('\u{a0}'),
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if chars.peek() == Some(&'-') {
+                    chars.next();
+                    out.push('—');
+                }
+                else {
+                    out.push('–');
+                }
+            },
+            '\\' => {
+                let accent = match chars.peek().cloned() {
+                    Some('"')  => Some('\u{308}'),
+                    Some('\'') => Some('\u{301}'),
+                    Some('`')  => Some('\u{300}'),
+                    Some('^')  => Some('\u{302}'),
+                    Some('~')  => Some('\u{303}'),
+                    Some('c')  => Some('\u{327}'),
+                    _          => None,
+                };
+                match accent {
+                    Some(mark) => {
+                        chars.next();
+                        while chars.peek() == Some(&'{') || chars.peek() == Some(&' ') {
+                            chars.next();
+                        }
+                        if let Some(letter) = chars.next() {
+                            out.push(letter);
+                            out.push(mark);
+                        }
+                    },
+                    None => {
+                        // \& \% \$ and friends stand for themselves; unknown commands are dropped
+                        match chars.peek().cloned() {
+                            Some(n) if !n.is_alphabetic() => {
+                                chars.next();
+                                out.push(n);
+                            },
+                            _ => {
+                                let mut name = String::new();
+                                while let Some(n) = chars.peek().cloned() {
+                                    if !n.is_alphabetic() {
+                                        break;
+                                    }
+                                    name.push(n);
+                                    chars.next();
+                                }
+                                match name.as_str() {
+                                    "TeX" | "LaTeX" | "BibTeX" => out.push_str(&name),
+                                    "ldots" | "dots"            => out.push('…'),
+                                    "textendash"                => out.push('–'),
+                                    "textemdash"                => out.push('—'),
+                                    _ => (),
+                                }
+                            },
+                        }
+                    },
+                }
+            },
+            c if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            },
+            c => out.push(c),
+        }
+    }
+    out.trim().to_owned()
+}
+
+// Splits `A. Author and Other, B.` into names, leaving braced groups like {Team and Co} whole
+fn names(field: &str) -> Vec<Name> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in field.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ' ' if depth == 0 && field[i..].get(..5).map_or(false, |s| s.eq_ignore_ascii_case(" and ")) => {
+                parts.push(&field[start..i]);
+                start = i + 5;
+            },
+            _ => (),
+        }
+    }
+    parts.push(&field[start..]);
+
+    parts.into_iter().map(|part| {
+        let part = part.trim();
+        if part.starts_with('{') && part.ends_with('}') {
+            return Name {first: String::new(), last: clean(part)};
+        }
+        let mut comma = part.splitn(2, ',');
+        let before    = comma.next().unwrap_or("");
+        match comma.next() {
+            Some(first) => Name {first: clean(first), last: clean(before)},
+            None => {
+                let part = clean(part);
+                match part.rfind(' ') {
+                    Some(i) => Name {first: part[..i].to_owned(), last: part[i + 1..].to_owned()},
+                    None    => Name {first: String::new(), last: part},
+                }
+            },
+        }
+    }).filter(|n| !n.last.is_empty()).collect()
+}
+
+pub fn load(path: &Path) -> Result<Bibliography, QuiltError> {
+    let mut bib_buf = String::new();
+    match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut bib_buf)) {
+        Ok(_)  => (),
+        Err(e) => return Err(QuiltError {source : "Bibliography".to_owned(),
+                                         message: format!("Could not read {}: {}", path.display(), e)}),
+    }
+
+    let mut strings = HashMap::new();
+    for (i, month) in ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"].iter().enumerate() {
+        strings.insert((*month).to_owned(), format!("{}", i + 1));
+    }
+
+    let mut parser = BibParser {chars: bib_buf.chars().peekable(), line: 1, path: path, strings: strings};
+    let entries = parser.entries()?;
+    Ok(Bibliography {path: path.to_path_buf(), entries: entries})
+}
+
+struct Cite<'t> {
+    key     : &'t str,
+    locator : Option<&'t str>,
+}
+
+// Parses the inside of `[@key, p. 3; @other]`, or returns None if it is not a citation
+fn parse_cites(inner: &str) -> Option<Vec<Cite>> {
+    let mut cites = vec![];
+    for part in inner.split(';') {
+        let part = part.trim();
+        if !part.starts_with('@') {
+            return None;
+        }
+        let end = part.find(|c: char| !(c.is_alphanumeric() || "_-:./+".contains(c)) && c != '@')
+                      .unwrap_or(part.len());
+        let key  = &part[1..end];
+        let rest = part[end..].trim_left_matches(',').trim();
+        if key.is_empty() {
+            return None;
+        }
+        cites.push(Cite {key: key, locator: if rest.is_empty() { None } else { Some(rest) }});
+    }
+    Some(cites)
+}
+
+fn short_authors(entry: &Entry) -> String {
+    match entry.authors.len() {
+        0 => entry.field("title").unwrap_or("Anonymous").to_owned(),
+        1 => entry.authors[0].last.clone(),
+        2 => format!("{} and {}", entry.authors[0].last, entry.authors[1].last),
+        _ => format!("{} et al.", entry.authors[0].last),
+    }
+}
+
+impl Bibliography {
+    // Replaces `[@key]` citations in text (outside code), returning the cited keys in order of first use
+    pub fn cite<'a>(&self, events: Vec<Event<'a>>, style: Style,
+                    md_path: &Path) -> Result<(Vec<Event<'a>>, Vec<String>), QuiltError> {
+        let mut out     = Vec::with_capacity(events.len());
+        let mut cited   : Vec<String> = vec![];
+        let mut in_code = false;
+
+        for event in events {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => {
+                    in_code = true;
+                    out.push(event);
+                },
+                Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => {
+                    in_code = false;
+                    out.push(event);
+                },
+                Event::Text(ref text) if !in_code && text.contains("[@") => {
+                    let mut rest = &text[..];
+                    while let Some(start) = rest.find("[@") {
+                        let end = match rest[start..].find(']') {
+                            Some(end) => start + end,
+                            None      => break,
+                        };
+                        let cites = match parse_cites(&rest[start + 1..end]) {
+                            Some(cites) => cites,
+                            None => {
+                                out.push(Event::Text(Cow::Owned(rest[..end + 1].to_owned())));
+                                rest = &rest[end + 1..];
+                                continue;
+                            },
+                        };
+
+                        if start > 0 {
+                            out.push(Event::Text(Cow::Owned(rest[..start].to_owned())));
+                        }
+                        for cite in &cites {
+                            if !self.entries.contains_key(cite.key) {
+                                return Err(QuiltError {source : "Citation".to_owned(),
+                                                       message: format!("{}: no entry @{} in {}", md_path.display(),
+                                                                        cite.key, self.path.display())});
+                            }
+                            if !cited.iter().any(|k| k == cite.key) {
+                                cited.push(cite.key.to_owned());
+                            }
+                        }
+                        out.push(Event::InlineHtml(Cow::Owned(self.render_cites(&cites, style, &cited))));
+                        rest = &rest[end + 1..];
+                    }
+                    if !rest.is_empty() {
+                        out.push(Event::Text(Cow::Owned(rest.to_owned())));
+                    }
+                },
+                other => out.push(other),
+            }
+        }
+
+        Ok((out, cited))
+    }
+
+    fn render_cites(&self, cites: &[Cite], style: Style, cited: &[String]) -> String {
+        let links : Vec<String> = cites.iter().map(|cite| {
+            let label = match style {
+                Style::AuthorDate => {
+                    let entry = &self.entries[cite.key];
+                    format!("{} {}", short_authors(entry), entry.year())
+                },
+                Style::Numeric => format!("{}", cited.iter().position(|k| k == cite.key).unwrap() + 1),
+            };
+            let mut link = format!("<a href=\"#ref-{}\">{}</a>", escape_html(cite.key), escape_html(&label));
+            if let Some(locator) = cite.locator {
+                link.push_str(&format!(", {}", escape_html(&clean(locator))));
+            }
+            link
+        }).collect();
+
+        match style {
+            Style::AuthorDate => format!("<span class=\"citation\">({})</span>", links.join("; ")),
+            Style::Numeric    => format!("<span class=\"citation\">[{}]</span>", links.join("; ")),
+        }
+    }
+
+    fn render_entry(&self, entry: &Entry, style: Style) -> String {
+        let title     = escape_html(entry.field("title").unwrap_or(""));
+        let container = entry.field("journal").or_else(|| entry.field("booktitle")).map(escape_html);
+        let publisher = entry.field("publisher").or_else(|| entry.field("institution"))
+                             .or_else(|| entry.field("school")).map(escape_html);
+        let pages     = entry.field("pages").map(escape_html);
+        let volume    = entry.field("volume").map(escape_html);
+        let number    = entry.field("number").map(escape_html);
+        let is_part   = container.is_some() || entry.kind == "article" || entry.kind == "inproceedings";
+
+        let mut out = String::new();
+        match style {
+            Style::AuthorDate => {
+                // Last, First, First Last, and First Last. Year. “Title.” Container Volume (Number): Pages.
+                let authors : Vec<String> = entry.authors.iter().enumerate().map(|(i, n)| {
+                    match (i, n.first.is_empty()) {
+                        (_, true) => n.last.clone(),
+                        (0, _)    => format!("{}, {}", n.last, n.first),
+                        _         => format!("{} {}", n.first, n.last),
+                    }
+                }).collect();
+                let authors = match authors.len() {
+                    0 => String::new(),
+                    1 => authors[0].clone(),
+                    n => format!("{}, and {}", authors[..n - 1].join(", "), authors[n - 1]),
+                };
+                if !authors.is_empty() {
+                    out.push_str(&format!("{}. ", escape_html(authors.trim_right_matches('.'))));
+                }
+                out.push_str(&format!("{}. ", escape_html(entry.year())));
+
+                if is_part {
+                    out.push_str(&format!("“{}.”", title));
+                }
+                else {
+                    out.push_str(&format!("<em>{}</em>.", title));
+                }
+                if let Some(container) = container {
+                    out.push_str(&format!(" <em>{}</em>", container));
+                    if let Some(volume) = volume {
+                        out.push_str(&format!(" {}", volume));
+                    }
+                    if let Some(number) = number {
+                        out.push_str(&format!(" ({})", number));
+                    }
+                    if let Some(pages) = pages {
+                        out.push_str(&format!(": {}", pages));
+                    }
+                    out.push('.');
+                }
+                if let Some(publisher) = publisher {
+                    out.push_str(&format!(" {}.", publisher));
+                }
+            },
+            Style::Numeric => {
+                // F. Last and F. Last, “Title,” Container, vol. V, no. N, pp. P, Year.
+                let authors : Vec<String> = entry.authors.iter().map(|n| {
+                    let initials : Vec<String> = n.first.split(|c: char| c.is_whitespace() || c == '.')
+                                                        .filter_map(|w| w.chars().next())
+                                                        .map(|c| format!("{}.", c)).collect();
+                    if initials.is_empty() { n.last.clone() } else { format!("{} {}", initials.join(" "), n.last) }
+                }).collect();
+                let authors = match authors.len() {
+                    0 => String::new(),
+                    1 => authors[0].clone(),
+                    2 => format!("{} and {}", authors[0], authors[1]),
+                    n => format!("{}, and {}", authors[..n - 1].join(", "), authors[n - 1]),
+                };
+
+                let mut parts = vec![];
+                if !authors.is_empty() {
+                    parts.push(escape_html(&authors));
+                }
+                if is_part {
+                    parts.push(format!("“{}”", title));
+                }
+                else {
+                    parts.push(format!("<em>{}</em>", title));
+                }
+                if let Some(container) = container {
+                    parts.push(format!("<em>{}</em>", container));
+                }
+                if let Some(volume) = volume {
+                    parts.push(format!("vol. {}", volume));
+                }
+                if let Some(number) = number {
+                    parts.push(format!("no. {}", number));
+                }
+                if let Some(pages) = pages {
+                    parts.push(format!("pp. {}", pages));
+                }
+                if let Some(publisher) = publisher {
+                    parts.push(publisher);
+                }
+                parts.push(escape_html(entry.year()));
+                out.push_str(&parts.join(", "));
+                out.push('.');
+            },
+        }
+
+        if let Some(doi) = entry.field("doi") {
+            let url = format!("https://doi.org/{}", doi);
+            out.push_str(&format!(" <a href=\"{}\">doi:{}</a>", escape_html(&url), escape_html(doi)));
+        }
+        else if let Some(url) = entry.field("url") {
+            out.push_str(&format!(" <a href=\"{0}\">{0}</a>", escape_html(url)));
+        }
+        out
+    }
+
+    // The reference list for the cited keys: numbered in citation order, or alphabetical by author
+    pub fn render(&self, cited: &[String], style: Style) -> String {
+        if cited.is_empty() {
+            return String::new();
+        }
+
+        let mut keys : Vec<&String> = cited.iter().collect();
+        if style == Style::AuthorDate {
+            keys.sort_by_key(|k| {
+                let entry = &self.entries[*k];
+                (short_authors(entry).to_lowercase(), entry.year().to_owned())
+            });
+        }
+
+        let list = if style == Style::Numeric { "ol" } else { "ul" };
+        let mut out = format!("<section class=\"bibliography\">\n<{} class=\"references\">\n", list);
+        for key in keys {
+            out.push_str(&format!("<li id=\"ref-{}\">{}</li>\n", escape_html(key),
+                                  self.render_entry(&self.entries[key], style)));
+        }
+        out.push_str(&format!("</{}>\n</section>\n", list));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lasts(field: &str) -> Vec<String> {
+        names(field).into_iter().map(|n| n.last).collect()
+    }
+
+    #[test]
+    fn names_split_on_and() {
+        assert_eq!(lasts("Knuth, Donald E. AND Lamport, Leslie"), vec!["Knuth", "Lamport"]);
+        assert_eq!(lasts("{Team and Co} and Ada Lovelace"), vec!["Team and Co", "Lovelace"]);
+    }
+
+    #[test]
+    fn names_with_non_ascii_before_and() {
+        // Both change length when lowercased: K (Kelvin sign) shrinks and İ grows
+        assert_eq!(lasts("Kel\u{212A}vin, A. and İnönü, B."), vec!["Kel\u{212A}vin", "İnönü"]);
+        assert_eq!(lasts("İİİİ İzmir and Ada Lovelace"), vec!["İzmir", "Lovelace"]);
+    }
+}
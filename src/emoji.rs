@@ -0,0 +1,105 @@
+use std::borrow::Cow;
+use pulldown_cmark::{Event, Tag};
+
+// GitHub's names for the more common emoji
+const EMOJI : &'static [(&'static str, &'static str)] = &[
+    ("+1", "👍"), ("-1", "👎"), ("100", "💯"), ("alarm_clock", "⏰"), ("angry", "😠"), ("apple", "🍎"),
+    ("arrow_down", "⬇️"), ("arrow_left", "⬅️"), ("arrow_right", "➡️"), ("arrow_up", "⬆️"), ("art", "🎨"),
+    ("baby", "👶"), ("balloon", "🎈"), ("bangbang", "‼️"), ("beer", "🍺"), ("bell", "🔔"), ("bike", "🚲"),
+    ("bird", "🐦"), ("blush", "😊"), ("bomb", "💣"), ("book", "📖"), ("books", "📚"), ("bookmark", "🔖"),
+    ("boom", "💥"), ("brain", "🧠"), ("bug", "🐛"), ("bulb", "💡"), ("bus", "🚌"), ("cake", "🍰"),
+    ("calendar", "📆"), ("camera", "📷"), ("car", "🚗"), ("cat", "🐱"), ("chart_with_upwards_trend", "📈"),
+    ("chart_with_downwards_trend", "📉"), ("clap", "👏"), ("clipboard", "📋"), ("cloud", "☁️"), ("coffee", "☕"),
+    ("computer", "💻"), ("confused", "😕"), ("construction", "🚧"), ("cookie", "🍪"), ("cool", "🆒"),
+    ("copyright", "©️"), ("crab", "🦀"), ("cry", "😢"), ("crystal_ball", "🔮"), ("dart", "🎯"), ("dash", "💨"),
+    ("dog", "🐶"), ("door", "🚪"), ("dragon", "🐉"), ("droplet", "💧"), ("earth_africa", "🌍"),
+    ("earth_americas", "🌎"), ("earth_asia", "🌏"), ("egg", "🥚"), ("email", "📧"), ("envelope", "✉️"),
+    ("exclamation", "❗"), ("eyes", "👀"), ("facepalm", "🤦"), ("file_folder", "📁"), ("fire", "🔥"),
+    ("fish", "🐟"), ("flashlight", "🔦"), ("floppy_disk", "💾"), ("flower_playing_cards", "🎴"), ("fox_face", "🦊"),
+    ("gear", "⚙️"), ("gem", "💎"), ("ghost", "👻"), ("gift", "🎁"), ("globe_with_meridians", "🌐"),
+    ("grey_question", "❔"), ("grin", "😁"), ("grinning", "😀"), ("hammer", "🔨"), ("hammer_and_wrench", "🛠️"),
+    ("hand", "✋"), ("heart", "❤️"), ("heart_eyes", "😍"), ("heavy_check_mark", "✔️"),
+    ("heavy_minus_sign", "➖"), ("heavy_plus_sign", "➕"), ("hourglass", "⌛"), ("house", "🏠"), ("hugs", "🤗"),
+    ("hushed", "😯"), ("information_source", "ℹ️"), ("innocent", "😇"), ("joy", "😂"), ("key", "🔑"),
+    ("keyboard", "⌨️"), ("kiss", "💋"), ("laughing", "😆"), ("leaves", "🍃"), ("link", "🔗"), ("lock", "🔒"),
+    ("loudspeaker", "📢"), ("mag", "🔍"), ("mailbox", "📫"), ("memo", "📝"), ("microscope", "🔬"),
+    ("moneybag", "💰"), ("moon", "🌔"), ("mountain", "⛰️"), ("muscle", "💪"), ("musical_note", "🎵"),
+    ("neutral_face", "😐"), ("new", "🆕"), ("no_entry", "⛔"), ("no_entry_sign", "🚫"), ("ok", "🆗"),
+    ("ok_hand", "👌"), ("open_book", "📖"), ("package", "📦"), ("paperclip", "📎"), ("partying_face", "🥳"),
+    ("pencil", "📝"), ("pencil2", "✏️"), ("penguin", "🐧"), ("phone", "☎️"), ("pizza", "🍕"), ("point_down", "👇"),
+    ("point_left", "👈"), ("point_right", "👉"), ("point_up", "☝️"), ("pray", "🙏"), ("pushpin", "📌"),
+    ("question", "❓"), ("rabbit", "🐰"), ("rainbow", "🌈"), ("raised_hands", "🙌"), ("recycle", "♻️"),
+    ("registered", "®️"), ("relaxed", "☺️"), ("relieved", "😌"), ("robot", "🤖"), ("rocket", "🚀"), ("rose", "🌹"),
+    ("rotating_light", "🚨"), ("sad", "😞"), ("scissors", "✂️"), ("scream", "😱"), ("see_no_evil", "🙈"),
+    ("seedling", "🌱"), ("shield", "🛡️"), ("shrug", "🤷"), ("skull", "💀"), ("sleeping", "😴"), ("smile", "😄"),
+    ("smiley", "😃"), ("smirk", "😏"), ("snail", "🐌"), ("snake", "🐍"), ("snowflake", "❄️"), ("snowman", "⛄"),
+    ("sob", "😭"), ("sparkles", "✨"), ("speech_balloon", "💬"), ("star", "⭐"), ("star2", "🌟"),
+    ("stopwatch", "⏱️"), ("sun_with_face", "🌞"), ("sunny", "☀️"), ("sunglasses", "😎"), ("sweat_smile", "😅"),
+    ("tada", "🎉"), ("tea", "🍵"), ("telescope", "🔭"), ("thinking", "🤔"), ("thought_balloon", "💭"),
+    ("thumbsdown", "👎"), ("thumbsup", "👍"), ("tm", "™️"), ("tongue", "👅"), ("trophy", "🏆"), ("turtle", "🐢"),
+    ("umbrella", "☂️"), ("unamused", "😒"), ("unlock", "🔓"), ("upside_down_face", "🙃"), ("v", "✌️"),
+    ("warning", "⚠️"), ("wave", "👋"), ("white_check_mark", "✅"), ("wink", "😉"), ("worried", "😟"),
+    ("wrench", "🔧"), ("x", "❌"), ("yum", "😋"), ("zap", "⚡"), ("zzz", "💤"),
+];
+
+fn lookup(name: &str) -> Option<&'static str> {
+    EMOJI.iter().find(|&&(n, _)| n == name).map(|&(_, e)| e)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-'
+}
+
+fn replace(text: &str) -> String {
+    let mut out  = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end   = after.find(|c: char| !is_name_char(c));
+
+        match end {
+            Some(end) if end > 0 && after[end..].starts_with(':') => {
+                match lookup(&after[..end]) {
+                    Some(emoji) => {
+                        out.push_str(emoji);
+                        rest = &after[end + 1..];
+                    },
+                    None => {
+                        // The closing colon may open the next shortcode, as in `:not_one::rocket:`
+                        out.push(':');
+                        out.push_str(&after[..end]);
+                        rest = &after[end..];
+                    },
+                }
+            },
+            _ => {
+                out.push(':');
+                rest = after;
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Replaces :name: shortcodes in text outside code with emoji, leaving unknown names as they are
+pub fn emoji<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut in_code = false;
+
+    events.into_iter().map(|event| {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => {
+                in_code = true;
+                event
+            },
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => {
+                in_code = false;
+                event
+            },
+            Event::Text(ref text) if !in_code && text.contains(':') => Event::Text(Cow::Owned(replace(text))),
+            other => other,
+        }
+    }).collect()
+}
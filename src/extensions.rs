@@ -0,0 +1,310 @@
+use std::borrow::Cow;
+use pulldown_cmark as markdown;
+use pulldown_cmark::{Event, Tag};
+
+use admonitions;
+use math;
+use emoji;
+
+// A [markdown] table, as found in Quilt.toml, a [[build]] or a page's toml
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct MarkdownToml {
+    tables            : Option<bool>,
+    footnotes         : Option<bool>,
+    strikethrough     : Option<bool>,
+    tasklists         : Option<bool>,
+    smart_punctuation : Option<bool>,
+    anchors           : Option<bool>,
+    admonitions       : Option<bool>,
+    math              : Option<bool>,
+    emoji             : Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MarkdownOptions {
+    pub tables            : bool,
+    pub footnotes         : bool,
+    pub strikethrough     : bool,
+    pub tasklists         : bool,
+    pub smart_punctuation : bool,
+    pub anchors           : bool,
+    pub admonitions       : bool,
+    pub math              : bool,
+    pub emoji             : bool,
+}
+
+impl MarkdownOptions {
+    // Settings given in `toml` take precedence over those already held
+    pub fn merge(&self, toml: &MarkdownToml) -> Self {
+        MarkdownOptions {
+            tables            : toml.tables.unwrap_or(self.tables),
+            footnotes         : toml.footnotes.unwrap_or(self.footnotes),
+            strikethrough     : toml.strikethrough.unwrap_or(self.strikethrough),
+            tasklists         : toml.tasklists.unwrap_or(self.tasklists),
+            smart_punctuation : toml.smart_punctuation.unwrap_or(self.smart_punctuation),
+            anchors           : toml.anchors.unwrap_or(self.anchors),
+            admonitions       : toml.admonitions.unwrap_or(self.admonitions),
+            math              : toml.math.unwrap_or(self.math),
+            emoji             : toml.emoji.unwrap_or(self.emoji),
+        }
+    }
+
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut names = vec![];
+        if self.tables            { names.push("tables"); }
+        if self.footnotes         { names.push("footnotes"); }
+        if self.strikethrough     { names.push("strikethrough"); }
+        if self.tasklists         { names.push("tasklists"); }
+        if self.smart_punctuation { names.push("smart_punctuation"); }
+        if self.anchors           { names.push("anchors"); }
+        if self.admonitions       { names.push("admonitions"); }
+        if self.math              { names.push("math"); }
+        if self.emoji             { names.push("emoji"); }
+        names
+    }
+
+    fn parser_options(&self) -> markdown::Options {
+        let mut opts = markdown::Options::empty();
+        if self.tables {
+            opts.insert(markdown::OPTION_ENABLE_TABLES);
+        }
+        if self.footnotes {
+            opts.insert(markdown::OPTION_ENABLE_FOOTNOTES);
+        }
+        opts
+    }
+}
+
+// Merges runs of adjacent text events, which the parser splits at special characters
+fn coalesce<'a, I: Iterator<Item=Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut out : Vec<Event<'a>> = vec![];
+    for event in events {
+        if let Event::Text(text) = event {
+            if let Some(&mut Event::Text(ref mut prev)) = out.last_mut() {
+                prev.to_mut().push_str(&text);
+                continue;
+            }
+            out.push(Event::Text(text));
+        }
+        else {
+            out.push(event);
+        }
+    }
+    out
+}
+
+// Pairs up ~~ across the text of a block, so struck text can hold emphasis, links or code. A pair
+// opens and closes inside the same emphasis or link, and an unpaired ~~ is left as written.
+fn strikethrough<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out : Vec<Event<'a>> = Vec::with_capacity(events.len());
+    // Where in `out` an unpaired ~~ sits, for each level of inline nesting
+    let mut opens   : Vec<Option<usize>> = vec![None];
+    let mut in_code = false;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => {
+                in_code = true;
+                out.push(event);
+            },
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => {
+                in_code = false;
+                out.push(event);
+            },
+            Event::Start(Tag::Emphasis) | Event::Start(Tag::Strong) | Event::Start(Tag::Link(..))
+                | Event::Start(Tag::Image(..)) => {
+                opens.push(None);
+                out.push(event);
+            },
+            Event::End(Tag::Emphasis) | Event::End(Tag::Strong) | Event::End(Tag::Link(..))
+                | Event::End(Tag::Image(..)) => {
+                if opens.len() > 1 {
+                    opens.pop();
+                }
+                out.push(event);
+            },
+            // Nothing pairs across blocks
+            Event::Start(_) | Event::End(_) => {
+                opens = vec![None];
+                out.push(event);
+            },
+            Event::Text(ref text) if !in_code && text.contains("~~") => {
+                for (i, part) in text.split("~~").enumerate() {
+                    if i > 0 {
+                        let open = opens.last_mut().unwrap();
+                        match open.take() {
+                            Some(at) => {
+                                out[at] = Event::InlineHtml(Cow::Borrowed("<del>"));
+                                out.push(Event::InlineHtml(Cow::Borrowed("</del>")));
+                            },
+                            None => {
+                                *open = Some(out.len());
+                                out.push(Event::Text(Cow::Borrowed("~~")));
+                            },
+                        }
+                    }
+                    if !part.is_empty() {
+                        out.push(Event::Text(Cow::Owned(part.to_owned())));
+                    }
+                }
+            },
+            other => out.push(other),
+        }
+    }
+    coalesce(out.into_iter())
+}
+
+fn tasklists<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut item_start = false;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Item) => {
+                item_start = true;
+                out.push(event);
+            },
+            Event::Start(Tag::Paragraph) if item_start => out.push(event),
+            Event::Text(ref text) if item_start => {
+                item_start = false;
+                let checkbox = {
+                    if text.starts_with("[ ] ") {
+                        Some("<input type=\"checkbox\" disabled=\"\" /> ")
+                    }
+                    else if text.starts_with("[x] ") || text.starts_with("[X] ") {
+                        Some("<input type=\"checkbox\" disabled=\"\" checked=\"\" /> ")
+                    }
+                    else {
+                        None
+                    }
+                };
+                match checkbox {
+                    Some(html) => {
+                        out.push(Event::InlineHtml(Cow::Borrowed(html)));
+                        out.push(Event::Text(Cow::Owned(text[4..].to_owned())));
+                    },
+                    None => out.push(Event::Text(text.clone())),
+                }
+            },
+            other => {
+                item_start = false;
+                out.push(other);
+            },
+        }
+    }
+    out
+}
+
+fn smarten(text: &str, prev: &mut char) -> String {
+    let mut out   = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let opening = prev.is_whitespace() || "([{-\u{2014}\u{2013}".contains(*prev);
+        match c {
+            '"'  => out.push(if opening { '\u{201C}' } else { '\u{201D}' }),
+            '\'' => out.push(if opening { '\u{2018}' } else { '\u{2019}' }),
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if chars.peek() == Some(&'-') {
+                    chars.next();
+                    out.push('\u{2014}');
+                }
+                else {
+                    out.push('\u{2013}');
+                }
+            },
+            '.' if chars.clone().take(2).collect::<String>() == ".." => {
+                chars.next();
+                chars.next();
+                out.push('\u{2026}');
+            },
+            _ => out.push(c),
+        }
+        *prev = out.chars().next_back().unwrap();
+    }
+    out
+}
+
+fn smart_punctuation<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut in_code = false;
+    let mut prev = ' ';
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => {
+                in_code = true;
+                out.push(event);
+            },
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => {
+                in_code = false;
+                prev = 'x';
+                out.push(event);
+            },
+            Event::Start(Tag::Paragraph) | Event::Start(Tag::Header(_)) | Event::Start(Tag::Item) |
+            Event::SoftBreak | Event::HardBreak => {
+                prev = ' ';
+                out.push(event);
+            },
+            Event::Text(ref text) if !in_code => out.push(Event::Text(Cow::Owned(smarten(text, &mut prev)))),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// Source-level rewrites that must happen before parsing
+pub fn preprocess(in_buf: String, opts: &MarkdownOptions) -> String {
+    let in_buf = if opts.admonitions { admonitions::separate_containers(&in_buf) } else { in_buf };
+    if opts.math { math::replace_math(&in_buf) } else { in_buf }
+}
+
+// Parses `in_buf` with the enabled parser options, then applies Quilt's own extensions
+pub fn parse<'a>(in_buf: &'a str, opts: &MarkdownOptions) -> Vec<Event<'a>> {
+    let mut events = coalesce(markdown::Parser::new_ext(in_buf, opts.parser_options()));
+
+    if opts.strikethrough {
+        events = strikethrough(events);
+    }
+    if opts.tasklists {
+        events = tasklists(events);
+    }
+    if opts.smart_punctuation {
+        events = smart_punctuation(events);
+    }
+    if opts.emoji {
+        events = emoji::emoji(events);
+    }
+    if opts.admonitions {
+        events = admonitions::admonitions(events);
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(in_buf: &str) -> String {
+        let opts = MarkdownOptions {strikethrough: true, ..MarkdownOptions::default()};
+        let mut html = String::new();
+        markdown::html::push_html(&mut html, parse(in_buf, &opts).into_iter());
+        html
+    }
+
+    #[test]
+    fn strikethrough_spans_markup() {
+        assert_eq!(render("a ~~b *c* d~~ e"), "<p>a <del>b <em>c</em> d</del> e</p>\n");
+        assert_eq!(render("~~`x` and [l](u)~~"), "<p><del><code>x</code> and <a href=\"u\">l</a></del></p>\n");
+        assert_eq!(render("~~a~~ b ~~c~~"), "<p><del>a</del> b <del>c</del></p>\n");
+    }
+
+    #[test]
+    fn unpaired_strikethrough_is_left_as_written() {
+        assert_eq!(render("a ~~b~~ c ~~d"), "<p>a <del>b</del> c ~~d</p>\n");
+        assert_eq!(render("*a ~~b* c~~"), "<p><em>a ~~b</em> c~~</p>\n");
+        assert_eq!(render("a ~~b\n\nc~~ d"), "<p>a ~~b</p>\n<p>c~~ d</p>\n");
+        assert_eq!(render("`~~a~~`"), "<p><code>~~a~~</code></p>\n");
+    }
+}
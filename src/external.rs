@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use super::{QuiltError, ConfigExternalLinks};
+
+// The host of an absolute http(s) or protocol-relative URL
+fn external_host(href: &str) -> Option<String> {
+    let lower = href.to_lowercase();
+    let rest  = {
+        if lower.starts_with("http://") {
+            &lower[7..]
+        }
+        else if lower.starts_with("https://") {
+            &lower[8..]
+        }
+        else if lower.starts_with("//") {
+            &lower[2..]
+        }
+        else {
+            return None;
+        }
+    };
+
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next().unwrap_or("");
+    let host      = authority.rsplit('@').next().unwrap_or("");
+    let host      = host.split(':').next().unwrap_or("").trim_right_matches('.');
+    if host.is_empty() { None } else { Some(host.to_owned()) }
+}
+
+// `example.com` allows example.com and any of its subdomains
+fn is_allowed(host: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|domain| {
+        let domain = domain.trim().trim_matches('.').to_lowercase();
+        host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+// Splits the inside of a tag like `<a ...>` into attributes, keeping their order
+pub fn parse_attrs(tag: &str) -> Vec<(String, Option<String>)> {
+    let mut attrs = vec![];
+    let mut chars = tag.chars().peekable();
+
+    loop {
+        while chars.peek().map(|c| c.is_whitespace() || *c == '/').unwrap_or(false) {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' || c == '/' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {
+            return attrs;
+        }
+
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        if chars.peek() != Some(&'=') {
+            attrs.push((name.to_lowercase(), None));
+            continue;
+        }
+        chars.next();
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        match chars.peek().cloned() {
+            Some(q) if q == '"' || q == '\'' => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == q {
+                        break;
+                    }
+                    value.push(c);
+                }
+            },
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            },
+        }
+        attrs.push((name.to_lowercase(), Some(value)));
+    }
+}
+
+fn set_attr(attrs: &mut Vec<(String, Option<String>)>, name: &str, value: String) {
+    match attrs.iter().position(|&(ref n, _)| n == name) {
+        Some(i) => attrs[i].1 = Some(value),
+        None    => attrs.push((name.to_owned(), Some(value))),
+    }
+}
+
+// Adds space-separated tokens to an attribute like class or rel, skipping ones already present
+fn add_tokens(attrs: &mut Vec<(String, Option<String>)>, name: &str, tokens: &str) {
+    let mut value = attrs.iter().find(|&&(ref n, _)| n == name)
+                         .and_then(|&(_, ref v)| v.clone()).unwrap_or_default();
+    for token in tokens.split_whitespace() {
+        if !value.split_whitespace().any(|t| t == token) {
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(token);
+        }
+    }
+    set_attr(attrs, name, value);
+}
+
+fn decorate_tag(attrs: &mut Vec<(String, Option<String>)>, config: &ConfigExternalLinks) {
+    if let Some(ref class) = config.class {
+        add_tokens(attrs, "class", class);
+    }
+    if config.new_tab {
+        set_attr(attrs, "target", "_blank".to_owned());
+        add_tokens(attrs, "rel", "noopener noreferrer");
+    }
+    if let Some(ref rel) = config.rel {
+        add_tokens(attrs, "rel", rel);
+    }
+}
+
+fn write_tag(attrs: &[(String, Option<String>)]) -> String {
+    let mut tag = String::from("<a");
+    for &(ref name, ref value) in attrs {
+        match *value {
+            Some(ref value) => tag.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;"))),
+            None            => tag.push_str(&format!(" {}", name)),
+        }
+    }
+    tag.push('>');
+    tag
+}
+
+// Rewrites the <a> tags in a page's rendered HTML that point off-site: decorating them as
+// configured in [external_links], and failing on hosts outside `allowed_domains` when it is set
+pub fn decorate(html: &str, config: &ConfigExternalLinks, md_path: &Path) -> Result<String, QuiltError> {
+    if config.class.is_none() && !config.new_tab && config.rel.is_none() && config.allowed_domains.is_empty() {
+        return Ok(html.to_owned());
+    }
+
+    let mut out  = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find("<a") {
+        let after = &rest[start + 2..];
+        if !after.starts_with(|c: char| c.is_whitespace()) {
+            out.push_str(&rest[..start + 2]);
+            rest = after;
+            continue;
+        }
+
+        // The tag ends at the first > outside a quoted value
+        let mut quote = None;
+        let end = after.char_indices().find(|&(_, c)| {
+            match quote {
+                Some(q) if c == q => { quote = None; false },
+                Some(_)           => false,
+                None if c == '"' || c == '\'' => { quote = Some(c); false },
+                None              => c == '>',
+            }
+        }).map(|(i, _)| i);
+        let end = match end {
+            Some(end) => end,
+            None      => break,
+        };
+
+        out.push_str(&rest[..start]);
+        let mut attrs = parse_attrs(&after[..end]);
+        let host      = attrs.iter().find(|&&(ref n, _)| n == "href")
+                             .and_then(|&(_, ref v)| v.as_ref().and_then(|href| external_host(href)));
+
+        match host {
+            Some(host) => {
+                if !config.allowed_domains.is_empty() && !is_allowed(&host, &config.allowed_domains) {
+                    return Err(QuiltError {source : "Link".to_owned(),
+                                           message: format!("{}: external link to {} is not in allowed_domains",
+                                                            md_path.display(), host)});
+                }
+                decorate_tag(&mut attrs, config);
+                out.push_str(&write_tag(&attrs));
+            },
+            None => out.push_str(&rest[start..start + 2 + end + 1]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
@@ -0,0 +1,115 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use pulldown_cmark as markdown;
+use pulldown_cmark::{Event, Tag};
+
+use super::{QuiltError, escape_html};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FootnoteStyle {
+    Endnotes,
+    Sidenotes,
+}
+
+impl FootnoteStyle {
+    pub fn parse(name: &str) -> Result<FootnoteStyle, QuiltError> {
+        match name {
+            "endnotes"  => Ok(FootnoteStyle::Endnotes),
+            "sidenotes" => Ok(FootnoteStyle::Sidenotes),
+            _ => Err(QuiltError {source : "Footnote".to_owned(),
+                                 message: format!("Unknown footnote style {} (expected endnotes or sidenotes)", name)}),
+        }
+    }
+}
+
+// A definition's content as inline HTML: paragraphs become line breaks so it fits in a <span>
+fn inline_html<'a>(body: Vec<Event<'a>>) -> String {
+    let mut events = Vec::with_capacity(body.len());
+    let mut first  = true;
+    for event in body {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                if !first {
+                    events.push(Event::Html(Cow::Borrowed("<br>")));
+                }
+                first = false;
+            },
+            Event::End(Tag::Paragraph) => (),
+            other => events.push(other),
+        }
+    }
+
+    let mut html = String::new();
+    markdown::html::push_html(&mut html, events.into_iter());
+    html.trim_right().to_owned()
+}
+
+// Tufte-style markup: a toggle label (numbered by CSS), a checkbox for narrow screens and the note itself
+fn sidenote(id: &str, content: &str) -> String {
+    format!("<label for=\"{0}\" class=\"margin-toggle sidenote-number\"></label>\
+             <input type=\"checkbox\" id=\"{0}\" class=\"margin-toggle\"/>\
+             <span class=\"sidenote\">{1}</span>", escape_html(id), content)
+}
+
+// Lifts footnote definitions out of the text and places each beside its references. Definitions
+// that are never referenced stay at the end of the page as ordinary footnotes.
+fn sidenotes<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut text        : Vec<Event<'a>> = Vec::with_capacity(events.len());
+    let mut definitions : HashMap<String, Vec<Event<'a>>> = HashMap::new();
+    let mut order       : Vec<String> = vec![];
+    let mut current     : Option<(String, Vec<Event<'a>>)> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(name)) => current = Some((name.into_owned(), vec![])),
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                if let Some((name, body)) = current.take() {
+                    order.push(name.clone());
+                    definitions.insert(name, body);
+                }
+            },
+            other => {
+                match current {
+                    Some((_, ref mut body)) => body.push(other),
+                    None                    => text.push(other),
+                }
+            },
+        }
+    }
+
+    let rendered : HashMap<String, String> = definitions.iter()
+                                                        .map(|(name, body)| (name.clone(), inline_html(body.clone())))
+                                                        .collect();
+    let mut used : HashMap<String, usize> = HashMap::new();
+
+    let mut out = Vec::with_capacity(text.len());
+    for event in text {
+        match event {
+            Event::FootnoteReference(ref name) if rendered.contains_key(&**name) => {
+                let count = used.entry(name.to_string()).or_insert(0);
+                *count += 1;
+                let id = if *count == 1 { format!("sn-{}", name) } else { format!("sn-{}-{}", name, count) };
+                out.push(Event::InlineHtml(Cow::Owned(sidenote(&id, &rendered[&**name]))));
+            },
+            other => out.push(other),
+        }
+    }
+
+    for name in order {
+        if !used.contains_key(&name) {
+            out.push(Event::Start(Tag::FootnoteDefinition(Cow::Owned(name.clone()))));
+            out.extend(definitions.remove(&name).unwrap());
+            out.push(Event::End(Tag::FootnoteDefinition(Cow::Owned(name))));
+        }
+    }
+    out
+}
+
+// Renders the page's events to HTML, placing footnotes in the requested style
+pub fn push_html<'a>(buf: &mut String, events: Vec<Event<'a>>, style: FootnoteStyle) {
+    let events = match style {
+        FootnoteStyle::Endnotes  => events,
+        FootnoteStyle::Sidenotes => sidenotes(events),
+    };
+    markdown::html::push_html(buf, events.into_iter());
+}
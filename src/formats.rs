@@ -0,0 +1,223 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{QuiltError, Page, RenderOptions, Rendered, read_template, wrap_content, blank_page_vars};
+use {notebook, shortcodes, extensions, links, stats, toc, highlight, footnotes, sanitize, external};
+
+// Extension of a page's sidecar, which goes with a content file of any format
+pub const SIDECAR : &'static str = "toml";
+
+// A kind of content file in site/, rendered to the HTML of a page
+pub trait ContentFormat {
+    // File extensions of the format, without the dot
+    fn extensions(&self) -> &'static [&'static str];
+
+    // The markdown in a file, which summaries and wiki links are taken from. Formats without any
+    // only have the summary their sidecar gives.
+    fn markdown_source<'a>(&self, _in_buf: &'a str) -> Option<Cow<'a, str>> {
+        None
+    }
+
+    // Renders `in_buf`, read from `path`, into the page's template
+    fn render(&self, page: &Page, in_buf: &str, path: &Path, temp: Option<PathBuf>,
+              vars: &mut HashMap<String, String>, opts: &RenderOptions) -> Result<Rendered, QuiltError>;
+}
+
+pub struct Markdown;
+
+impl ContentFormat for Markdown {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md"]
+    }
+
+    fn markdown_source<'a>(&self, in_buf: &'a str) -> Option<Cow<'a, str>> {
+        Some(Cow::Borrowed(in_buf))
+    }
+
+    fn render(&self, _page: &Page, in_buf: &str, path: &Path, temp: Option<PathBuf>,
+              vars: &mut HashMap<String, String>, opts: &RenderOptions) -> Result<Rendered, QuiltError> {
+        self.render_with_blocks(in_buf, &[], path, temp, vars, opts)
+    }
+}
+
+const BLOCK_MARKER : &'static str = "<!-- quilt:block ";
+
+// The placeholder for blocks[n] in Markdown::render_with_blocks
+pub fn block_placeholder(n: usize) -> String {
+    format!("{}{} -->", BLOCK_MARKER, n)
+}
+
+fn splice_blocks(html: &str, blocks: &[String]) -> String {
+    let mut out  = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(BLOCK_MARKER) {
+        let after = &rest[start + BLOCK_MARKER.len()..];
+        let end   = after.find(" -->");
+        let block = end.and_then(|end| after[..end].parse::<usize>().ok()).and_then(|n| blocks.get(n));
+        match (block, end) {
+            (Some(block), Some(end)) => {
+                out.push_str(&rest[..start]);
+                out.push_str(block);
+                rest = &after[end + 4..];
+            },
+            _ => {
+                out.push_str(&rest[..start + BLOCK_MARKER.len()]);
+                rest = after;
+            },
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Markdown {
+    // Formats built on markdown can leave <!-- quilt:block N --> lines in it for HTML which must not
+    // be read as markdown (or touched by math and shortcodes). They are replaced by `blocks[N]` once
+    // the markdown is HTML, so blocks are still sanitised and have their links decorated.
+    pub fn render_with_blocks(&self, in_buf: &str, blocks: &[String], path: &Path, temp: Option<PathBuf>,
+                              vars: &mut HashMap<String, String>,
+                              opts: &RenderOptions) -> Result<Rendered, QuiltError> {
+        let wrap_str = read_template(&temp)?;
+
+        let (in_buf, mut deps) = shortcodes::expand(in_buf, opts.shortcode_dir.as_ref().map(|d| d.as_path()),
+                                                    &opts.site_root, path)?;
+        let in_buf = extensions::preprocess(in_buf, &opts.markdown);
+
+        let events = extensions::parse(&in_buf, &opts.markdown);
+        let events = links::rewrite(events, opts.page_path, path, opts.page_urls, opts.page_assets)?;
+        let events = opts.wiki.rewrite(events, opts.page_path, path, opts.page_urls)?;
+        let (events, cited) = match opts.bibliography {
+            Some(bib) => bib.cite(events, opts.citation_style, path)?,
+            None      => (events, vec![]),
+        };
+        let events = match opts.glossary {
+            Some(glossary) => {
+                deps.push(glossary.path.clone());
+                glossary.link(events)
+            },
+            None => events,
+        };
+        let stats  = stats::measure(&events, opts.words_per_minute);
+        let (events, toc) = toc::anchor_headings(events, opts.markdown.anchors);
+        let events = {
+            if opts.highlight.enabled {
+                highlight::highlight(events, &opts.highlight)
+            }
+            else {
+                events
+            }
+        };
+
+        let mut parse_buf = String::new();
+        footnotes::push_html(&mut parse_buf, events, opts.footnote_style);
+        if !blocks.is_empty() {
+            parse_buf = splice_blocks(&parse_buf, blocks);
+        }
+        if let Some(policy) = opts.sanitize {
+            parse_buf = sanitize::sanitize(&parse_buf, policy);
+        }
+        let mut parse_buf = external::decorate(&parse_buf, opts.external_links, path)?;
+
+        // The reference list goes where the template asks for it, or else after the content
+        let references = match opts.bibliography {
+            Some(bib) if !cited.is_empty() => {
+                deps.push(bib.path.clone());
+                external::decorate(&bib.render(&cited, opts.citation_style), opts.external_links, &bib.path)?
+            },
+            _ => String::new(),
+        };
+        if wrap_str.contains("{{bibliography}}") {
+            vars.insert("bibliography".to_owned(), references);
+        }
+        else {
+            parse_buf.push_str(&references);
+        }
+
+        vars.insert("page.word_count".to_owned()  , format!("{}", stats.word_count));
+        vars.insert("page.reading_time".to_owned(), format!("{}", stats.reading_time));
+        vars.insert("toc".to_owned(), toc::render(&toc));
+
+        let out_buf = wrap_content(&wrap_str, &parse_buf, vars, &temp)?;
+        Ok(Rendered {html: out_buf, stats: stats, deps: deps, files: vec![]})
+    }
+}
+
+// HTML wrapped by the page's template, unless the sidecar asks for `render = "raw"`
+pub struct Html;
+
+impl ContentFormat for Html {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html"]
+    }
+
+    fn render(&self, page: &Page, in_buf: &str, path: &Path, temp: Option<PathBuf>,
+              vars: &mut HashMap<String, String>, opts: &RenderOptions) -> Result<Rendered, QuiltError> {
+        match page.page_toml.render.as_ref().map(|r| r.as_str()) {
+            Some("raw") => {
+                if opts.sanitize.is_some() {
+                    return Err(QuiltError {source : "Generator".to_owned(),
+                                           message: format!("{}: raw pages are not allowed in sanitized sections",
+                                                            path.display())});
+                }
+                Ok(Rendered {html: in_buf.to_owned(), stats: stats::PageStats::default(), deps: vec![],
+                                 files: vec![]})
+            },
+            None | Some("template") => {
+                let wrap_str = read_template(&temp)?;
+                let content  = match opts.sanitize {
+                    Some(policy) => sanitize::sanitize(in_buf, policy),
+                    None         => in_buf.to_owned(),
+                };
+                let content  = external::decorate(&content, opts.external_links, path)?;
+
+                blank_page_vars(vars);
+                let out_buf = wrap_content(&wrap_str, &content, vars, &temp)?;
+                Ok(Rendered {html: out_buf, stats: stats::PageStats::default(), deps: vec![], files: vec![]})
+            },
+            Some(other) => Err(QuiltError {source : "Generator".to_owned(),
+                                           message: format!("{}: unknown render mode {} (expected raw or template)",
+                                                            path.display(), other)}),
+        }
+    }
+}
+
+// The formats a build understands. Further formats (AsciiDoc, reStructuredText, ...) implement
+// ContentFormat in their own module and are registered in Registry::new. Every format built in so
+// far is always on, so there is no [features] table yet; one that pulls in its own dependencies
+// would add a feature for them to Cargo.toml and register behind it:
+//
+//     #[cfg(feature = "asciidoc")]
+//     formats.push(Box::new(asciidoc::AsciiDoc));
+pub struct Registry {
+    formats : Vec<Box<ContentFormat>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let formats : Vec<Box<ContentFormat>> = vec![Box::new(Markdown), Box::new(Html),
+                                                      Box::new(notebook::Notebook)];
+        Registry {formats: formats}
+    }
+
+    pub fn get(&self, ext: &str) -> Option<&ContentFormat> {
+        self.formats.iter().find(|f| f.extensions().contains(&ext)).map(|f| &**f)
+    }
+
+    pub fn is_content(&self, ext: &str) -> bool {
+        self.get(ext).is_some()
+    }
+
+    // The format of the page's content file, if it has one
+    pub fn of(&self, page: &Page) -> Option<&ContentFormat> {
+        page.content.as_ref().and_then(|ext| self.get(ext))
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exts : Vec<&str> = self.formats.iter().flat_map(|f| f.extensions().iter().cloned()).collect();
+        write!(f, "Registry {:?}", exts)
+    }
+}
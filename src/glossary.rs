@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use pulldown_cmark::{Event, Tag};
+use toml;
+
+use super::{QuiltError, escape_html};
+use toc::slugify;
+
+#[derive(Debug, Deserialize)]
+struct GlossaryToml {
+    definition : Option<String>,
+    url        : Option<String>,
+}
+
+#[derive(Debug)]
+struct Term {
+    term       : String,
+    definition : String,
+    url        : Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Glossary {
+    pub path : PathBuf,
+    // Longest first, so that `HTTP/2` wins over `HTTP`
+    terms    : Vec<Term>,
+    // The generated glossary page, when terms should link to it
+    page_url : Option<String>,
+}
+
+fn glossary_err(path: &Path, message: String) -> QuiltError {
+    QuiltError {source : "Glossary".to_owned(),
+                message: format!("{}: {}", path.display(), message)}
+}
+
+fn term_id(term: &str) -> String {
+    format!("term-{}", slugify(term))
+}
+
+// Byte offset of the first whole-word occurrence of `term` in `text`
+fn find_word(text: &str, term: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(i) = text[from..].find(term) {
+        let start  = from + i;
+        let end    = start + term.len();
+        let before = text[..start].chars().next_back().map(|c| c.is_alphanumeric()).unwrap_or(false);
+        let after  = text[end..].chars().next().map(|c| c.is_alphanumeric()).unwrap_or(false);
+        if !before && !after {
+            return Some(start);
+        }
+        from = start + text[start..].chars().next().unwrap().len_utf8();
+    }
+    None
+}
+
+// Reads a TOML file of `[term]` tables, each with a `definition` and/or a `url`
+pub fn load(path: &Path, page_url: Option<String>) -> Result<Glossary, QuiltError> {
+    let mut toml_buf = String::new();
+    match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut toml_buf)) {
+        Ok(_)  => (),
+        Err(e) => return Err(glossary_err(path, format!("could not read: {}", e))),
+    }
+
+    let entries : HashMap<String, GlossaryToml> = match toml::from_str(&toml_buf) {
+        Ok(entries) => entries,
+        Err(err)    => return Err(glossary_err(path, format!("could not decode: {}", err))),
+    };
+
+    let mut terms = vec![];
+    for (term, entry) in entries {
+        if term.trim().is_empty() {
+            return Err(glossary_err(path, "empty term".to_owned()));
+        }
+        if entry.definition.is_none() && entry.url.is_none() {
+            return Err(glossary_err(path, format!("{} needs a definition or a url", term)));
+        }
+        terms.push(Term {term: term.trim().to_owned(), definition: entry.definition.unwrap_or_default(), url: entry.url});
+    }
+    terms.sort_by(|a, b| b.term.len().cmp(&a.term.len()).then(a.term.cmp(&b.term)));
+
+    Ok(Glossary {path: path.to_path_buf(), terms: terms, page_url: page_url})
+}
+
+impl Glossary {
+    fn open<'a>(&self, term: &Term) -> Event<'a> {
+        let title = escape_html(&term.definition);
+        let html  = match (&term.url, &self.page_url) {
+            (&Some(ref url), _) => format!("<a class=\"glossary\" href=\"{}\" title=\"{}\">", escape_html(url), title),
+            (&None, &Some(ref page)) => format!("<a class=\"glossary\" href=\"{}#{}\" title=\"{}\">",
+                                                escape_html(page), term_id(&term.term), title),
+            (&None, &None) => format!("<abbr title=\"{}\">", title),
+        };
+        Event::InlineHtml(Cow::Owned(html))
+    }
+
+    fn close<'a>(&self, term: &Term) -> Event<'a> {
+        if term.url.is_some() || self.page_url.is_some() {
+            Event::InlineHtml(Cow::Borrowed("</a>"))
+        }
+        else {
+            Event::InlineHtml(Cow::Borrowed("</abbr>"))
+        }
+    }
+
+    // Marks up the first occurrence of each term in the page's text, leaving alone code,
+    // headings and the text of existing links
+    pub fn link<'a>(&self, events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+        let mut out  = Vec::with_capacity(events.len());
+        let mut seen = vec![false; self.terms.len()];
+        let mut skip = 0;
+
+        for event in events {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) |
+                Event::Start(Tag::Header(_))    | Event::Start(Tag::Link(_, _)) | Event::Start(Tag::Image(_, _)) => {
+                    skip += 1;
+                    out.push(event);
+                },
+                Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) |
+                Event::End(Tag::Header(_))    | Event::End(Tag::Link(_, _)) | Event::End(Tag::Image(_, _)) => {
+                    skip -= 1;
+                    out.push(event);
+                },
+                Event::Text(text) if skip == 0 => {
+                    let mut rest = text.into_owned();
+                    loop {
+                        // The earliest unseen term in what remains, preferring the longest at a position
+                        let found = self.terms.iter().enumerate()
+                                        .filter(|&(i, _)| !seen[i])
+                                        .filter_map(|(i, t)| find_word(&rest, &t.term).map(|pos| (pos, i)))
+                                        .min_by_key(|&(pos, i)| (pos, i));
+                        let (pos, i) = match found {
+                            Some(found) => found,
+                            None        => break,
+                        };
+                        seen[i] = true;
+
+                        let term = &self.terms[i];
+                        let end  = pos + term.term.len();
+                        if pos > 0 {
+                            out.push(Event::Text(Cow::Owned(rest[..pos].to_owned())));
+                        }
+                        out.push(self.open(term));
+                        out.push(Event::Text(Cow::Owned(rest[pos..end].to_owned())));
+                        out.push(self.close(term));
+                        rest = rest[end..].to_owned();
+                    }
+                    if !rest.is_empty() {
+                        out.push(Event::Text(Cow::Owned(rest)));
+                    }
+                },
+                other => out.push(other),
+            }
+        }
+        out
+    }
+
+    // The body of the generated glossary page, alphabetical by term
+    pub fn render_page(&self) -> String {
+        let mut terms : Vec<&Term> = self.terms.iter().collect();
+        terms.sort_by_key(|t| t.term.to_lowercase());
+
+        let mut out = String::from("<h1>Glossary</h1>\n<dl class=\"glossary\">\n");
+        for term in terms {
+            let name = match term.url {
+                Some(ref url) => format!("<a href=\"{}\">{}</a>", escape_html(url), escape_html(&term.term)),
+                None          => escape_html(&term.term),
+            };
+            out.push_str(&format!("<dt id=\"{}\">{}</dt>\n<dd>{}</dd>\n", term_id(&term.term), name,
+                                  escape_html(&term.definition)));
+        }
+        out.push_str("</dl>\n");
+        out
+    }
+}
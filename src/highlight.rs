@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+use pulldown_cmark::{Event, Tag};
+
+use super::{ConfigHighlight, escape_html};
+
+struct Lang {
+    names         : &'static [&'static str],
+    keywords      : &'static [&'static str],
+    line_comment  : Option<&'static str>,
+    block_comment : Option<(&'static str, &'static str)>,
+    quotes        : &'static [char],
+}
+
+const LANGS: &'static [Lang] = &[
+    Lang {
+        names    : &["rust", "rs"],
+        keywords : &["as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+                     "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+                     "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+                     "where", "while"],
+        line_comment  : Some("//"),
+        block_comment : Some(("/*", "*/")),
+        quotes        : &['"'],
+    },
+    Lang {
+        names    : &["python", "py"],
+        keywords : &["and", "as", "assert", "break", "class", "continue", "def", "del", "elif", "else", "except",
+                     "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None",
+                     "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while", "with", "yield"],
+        line_comment  : Some("#"),
+        block_comment : None,
+        quotes        : &['"', '\''],
+    },
+    Lang {
+        names    : &["javascript", "js", "typescript", "ts"],
+        keywords : &["async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+                     "delete", "do", "else", "export", "extends", "false", "finally", "for", "function", "if",
+                     "import", "in", "instanceof", "interface", "let", "new", "null", "return", "switch", "this",
+                     "throw", "true", "try", "type", "typeof", "undefined", "var", "while", "yield"],
+        line_comment  : Some("//"),
+        block_comment : Some(("/*", "*/")),
+        quotes        : &['"', '\'', '`'],
+    },
+    Lang {
+        names    : &["c", "h", "cpp", "c++", "cc", "hpp", "java"],
+        keywords : &["auto", "bool", "break", "case", "char", "class", "const", "continue", "default", "do",
+                     "double", "else", "enum", "extends", "extern", "false", "final", "float", "for", "if",
+                     "implements", "import", "int", "long", "namespace", "new", "nullptr", "private",
+                     "protected", "public", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+                     "template", "this", "true", "typedef", "union", "unsigned", "using", "virtual", "void",
+                     "volatile", "while"],
+        line_comment  : Some("//"),
+        block_comment : Some(("/*", "*/")),
+        quotes        : &['"', '\''],
+    },
+    Lang {
+        names    : &["go", "golang"],
+        keywords : &["break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough",
+                     "false", "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+                     "range", "return", "select", "struct", "switch", "true", "type", "var"],
+        line_comment  : Some("//"),
+        block_comment : Some(("/*", "*/")),
+        quotes        : &['"', '\'', '`'],
+    },
+    Lang {
+        names    : &["sh", "bash", "shell", "zsh"],
+        keywords : &["case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+                     "local", "return", "then", "until", "while"],
+        line_comment  : Some("#"),
+        block_comment : None,
+        quotes        : &['"', '\''],
+    },
+    Lang {
+        names    : &["toml", "ini"],
+        keywords : &["true", "false"],
+        line_comment  : Some("#"),
+        block_comment : None,
+        quotes        : &['"', '\''],
+    },
+    Lang {
+        names    : &["json"],
+        keywords : &["true", "false", "null"],
+        line_comment  : None,
+        block_comment : None,
+        quotes        : &['"'],
+    },
+];
+
+// Colours for each token class, per built-in scheme
+const THEMES: &'static [(&'static str, &'static [(&'static str, &'static str)])] = &[
+    ("light", &[("kw", "#a626a4"), ("str", "#50a14f"), ("com", "#a0a1a7"), ("num", "#986801"),
+                ("ln", "#9d9d9f"), ("hl", "#fff8c5")]),
+    ("dark",  &[("kw", "#c678dd"), ("str", "#98c379"), ("com", "#7f848e"), ("num", "#d19a66"),
+                ("ln", "#636d83"), ("hl", "#3e4451")]),
+];
+
+pub fn is_theme(name: &str) -> bool {
+    THEMES.iter().any(|&(n, _)| n == name)
+}
+
+// Stylesheet for the class-based markup, written to static/highlight.css
+pub fn css(theme: &str) -> String {
+    let mut css = String::from("pre.highlight .line { display: flex; }\n\
+                                pre.highlight .ln { display: inline-block; width: 3em; user-select: none; }\n");
+    if let Some(&(_, colours)) = THEMES.iter().find(|&&(n, _)| n == theme) {
+        for &(class, colour) in colours {
+            match class {
+                "hl" => css.push_str(&format!("pre.highlight .line.hl {{ background-color: {}; }}\n", colour)),
+                _    => css.push_str(&format!("pre.highlight .{} {{ color: {}; }}\n", class, colour)),
+            }
+        }
+    }
+    css
+}
+
+fn find_lang(name: &str) -> Option<&'static Lang> {
+    let name = name.to_lowercase();
+    LANGS.iter().find(|l| l.names.contains(&name.as_str()))
+}
+
+fn tokenise(code: &str, lang: &Lang) -> Vec<(Option<&'static str>, String)> {
+    let mut tokens : Vec<(Option<&'static str>, String)> = vec![];
+    let mut plain  = String::new();
+    let mut i = 0;
+
+    while i < code.len() {
+        let rest = &code[i..];
+        let c    = rest.chars().next().unwrap();
+
+        let line_comment  = lang.line_comment.map(|o| rest.starts_with(o)).unwrap_or(false);
+        let block_comment = lang.block_comment.map(|(o, _)| rest.starts_with(o)).unwrap_or(false);
+
+        let (class, len) = {
+            if line_comment {
+                (Some("com"), rest.find('\n').unwrap_or(rest.len()))
+            }
+            else if block_comment {
+                let (open, close) = lang.block_comment.unwrap();
+                let end = rest[open.len()..].find(close).map(|e| e + open.len() + close.len()).unwrap_or(rest.len());
+                (Some("com"), end)
+            }
+            else if lang.quotes.contains(&c) {
+                let mut end     = rest.len();
+                let mut escaped = false;
+                for (j, d) in rest.char_indices().skip(1) {
+                    if escaped {
+                        escaped = false;
+                    }
+                    else if d == '\\' {
+                        escaped = true;
+                    }
+                    else if d == c {
+                        end = j + d.len_utf8();
+                        break;
+                    }
+                }
+                (Some("str"), end)
+            }
+            else if c.is_ascii_digit() {
+                let end = rest.find(|d: char| !(d.is_ascii_alphanumeric() || d == '.' || d == '_'))
+                              .unwrap_or(rest.len());
+                (Some("num"), end)
+            }
+            else if c.is_alphabetic() || c == '_' {
+                let end  = rest.find(|d: char| !(d.is_alphanumeric() || d == '_')).unwrap_or(rest.len());
+                let word = &rest[..end];
+                (if lang.keywords.contains(&word) { Some("kw") } else { None }, end)
+            }
+            else {
+                (None, c.len_utf8())
+            }
+        };
+
+        match class {
+            Some(_) => {
+                if !plain.is_empty() {
+                    tokens.push((None, plain.clone()));
+                    plain.clear();
+                }
+                tokens.push((class, rest[..len].to_owned()));
+            },
+            None => plain.push_str(&rest[..len]),
+        }
+        i += len;
+    }
+
+    if !plain.is_empty() {
+        tokens.push((None, plain));
+    }
+    tokens
+}
+
+// Parses the `{3-5,8}` of an info string like "rust {3-5,8}" into inclusive ranges of line numbers.
+// The ranges are kept rather than expanded, so `{1-1000000000}` costs no more than `{1-10}`.
+fn marked_lines(info: &str) -> Vec<(usize, usize)> {
+    let mut lines = vec![];
+    if let (Some(open), Some(close)) = (info.find('{'), info.rfind('}')) {
+        if open < close {
+            for range in info[open + 1..close].split(',') {
+                let mut ends = range.trim().splitn(2, '-').map(|n| n.trim().parse::<usize>());
+                match (ends.next(), ends.next()) {
+                    (Some(Ok(a)), None)        => lines.push((a, a)),
+                    (Some(Ok(a)), Some(Ok(b))) => lines.push((a, b)),
+                    _ => (),
+                }
+            }
+        }
+    }
+    lines
+}
+
+fn render_block(info: &str, code: &str, line_numbers: bool) -> String {
+    let lang_name = info.split_whitespace().next().unwrap_or("");
+    let ranges    = marked_lines(info);
+    let numbered  = line_numbers || info.split_whitespace().any(|w| w == "linenos");
+
+    let tokens = match find_lang(lang_name) {
+        Some(lang) => tokenise(code, lang),
+        None       => vec![(None, code.to_owned())],
+    };
+
+    // Split tokens at newlines so that each line is closed and reopened independently
+    let mut lines : Vec<String> = vec![String::new()];
+    for (class, text) in tokens {
+        for (n, part) in text.split('\n').enumerate() {
+            if n > 0 {
+                lines.push(String::new());
+            }
+            if part.is_empty() {
+                continue;
+            }
+            let line = lines.last_mut().unwrap();
+            match class {
+                Some(class) => line.push_str(&format!("<span class=\"{}\">{}</span>", class, escape_html(part))),
+                None        => line.push_str(&escape_html(part)),
+            }
+        }
+    }
+    if code.ends_with('\n') {
+        lines.pop();
+    }
+
+    let mut out = String::from("<pre class=\"highlight\"><code");
+    if !lang_name.is_empty() {
+        out.push_str(&format!(" class=\"language-{}\"", escape_html(lang_name)));
+    }
+    out.push('>');
+    for (i, line) in lines.iter().enumerate() {
+        let marked = ranges.iter().any(|&(a, b)| a <= i + 1 && i + 1 <= b);
+        let class  = if marked { "line hl" } else { "line" };
+        out.push_str(&format!("<span class=\"{}\">", class));
+        if numbered {
+            out.push_str(&format!("<span class=\"ln\">{}</span>", i + 1));
+        }
+        out.push_str(line);
+        out.push_str("\n</span>");
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+// Replaces fenced code blocks with class-based highlighted markup
+pub fn highlight<'a>(events: Vec<Event<'a>>, config: &ConfigHighlight) -> Vec<Event<'a>> {
+    let mut out  = Vec::with_capacity(events.len());
+    let mut code : Option<(Cow<'a, str>, String)> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(info)) => code = Some((info, String::new())),
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((info, text)) = code.take() {
+                    out.push(Event::Html(Cow::Owned(render_block(&info, &text, config.line_numbers))));
+                }
+            },
+            Event::Text(text) => {
+                match code {
+                    Some((_, ref mut buf)) => buf.push_str(&text),
+                    None => out.push(Event::Text(text)),
+                }
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_marked_ranges_stay_cheap() {
+        let html = render_block("rust {1-18446744073709551615}", "a\nb\n", false);
+        assert_eq!(html.matches("line hl").count(), 2);
+        let html = render_block("rust {2,1000000000-1}", "a\nb\n", false);
+        assert_eq!(html.matches("line hl").count(), 1);
+    }
+}
@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf, Component};
+use pulldown_cmark::{Event, Tag};
+
+use super::{QuiltError, Page, page_url};
+
+// Files which may be bundled beside pages: images, media, documents, data and web assets
+pub const DEFAULT_ASSET_EXTENSIONS : &'static [&'static str] = &[
+    "png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "ico", "bmp", "tif", "tiff",
+    "mp3", "mp4", "m4a", "ogg", "oga", "ogv", "wav", "webm", "mov", "vtt",
+    "pdf", "txt", "csv", "tsv", "json", "xml", "bib", "zip", "gz", "tgz",
+    "css", "js", "woff", "woff2", "ttf", "otf",
+];
+
+// Output URL of every page with a content file, keyed as in Site::pages
+pub fn page_urls(pages: &HashMap<PathBuf, Page>) -> HashMap<PathBuf, String> {
+    pages.iter()
+         .filter(|&(_, page)| page.has_content())
+         .map(|(path, _)| (path.clone(), page_url(path)))
+         .collect()
+}
+
+// Output URL of every bundled asset, keyed by its path (eg. site/blog/post/diagram.png => /blog/post/diagram.png)
+pub fn asset_urls(assets: &[PathBuf]) -> HashMap<PathBuf, String> {
+    assets.iter().map(|path| {
+        let adjusted = path.strip_prefix("site").unwrap_or(path);
+        let url : Vec<String> = adjusted.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        (path.clone(), format!("/{}", url.join("/")))
+    }).collect()
+}
+
+pub fn normalise(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir    => (),
+            Component::ParentDir => { out.pop(); },
+            other                => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
+
+fn is_source_link(dest: &str) -> bool {
+    let target = dest.split('#').next().unwrap_or("");
+    if dest.starts_with("@/") {
+        return true;
+    }
+    !dest.contains("://") && !dest.starts_with("mailto:") && !dest.starts_with('/') && target.ends_with(".md")
+}
+
+// Maps `@/docs/guide.md` (from site/) or `../guide.md` (from the linking page) to a Site::pages key
+fn page_key(target: &str, page_path: &Path) -> PathBuf {
+    let mut key = {
+        if target.starts_with("@/") {
+            Path::new("site").join(&target[2..])
+        }
+        else {
+            page_path.parent().unwrap_or(Path::new("site")).join(target)
+        }
+    };
+    key.set_extension("");
+    normalise(&key)
+}
+
+// A relative link to an asset bundled beside the page, as its URL, keeping any query or fragment
+fn asset_link(dest: &str, page_path: &Path, assets: &HashMap<PathBuf, String>) -> Option<String> {
+    if dest.contains("://") || dest.starts_with('/') || dest.starts_with('#') || dest.starts_with("mailto:") {
+        return None;
+    }
+    let split  = dest.find(|c| c == '?' || c == '#').unwrap_or(dest.len());
+    let target = &dest[..split];
+    let key    = normalise(&page_path.parent().unwrap_or(Path::new("site")).join(target));
+    assets.get(&key).map(|url| format!("{}{}", url, &dest[split..]))
+}
+
+// Rewrites links to markdown sources into links to their pages, failing on links to unknown pages.
+// Relative links and images pointing at bundled assets become absolute, so they work wherever
+// the page's HTML ends up, summaries included.
+pub fn rewrite<'a>(events: Vec<Event<'a>>, page_path: &Path, md_path: &Path, urls: &HashMap<PathBuf, String>,
+                   assets: &HashMap<PathBuf, String>) -> Result<Vec<Event<'a>>, QuiltError> {
+    let mut out = Vec::with_capacity(events.len());
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Link(ref dest, ref title)) if is_source_link(dest) => {
+                let mut parts  = dest.splitn(2, '#');
+                let target     = parts.next().unwrap();
+                let fragment   = parts.next();
+
+                let key = page_key(target, page_path);
+                let mut url = match urls.get(&key) {
+                    Some(url) => url.clone(),
+                    None => return Err(QuiltError {source : "Link".to_owned(),
+                                                   message: format!("{}: link to nonexistent page {}",
+                                                                    md_path.display(), dest)}),
+                };
+                if let Some(fragment) = fragment {
+                    url.push('#');
+                    url.push_str(fragment);
+                }
+                out.push(Event::Start(Tag::Link(Cow::Owned(url), title.clone())));
+            },
+            Event::Start(Tag::Link(dest, title)) => {
+                let dest = asset_link(&dest, page_path, assets).map(Cow::Owned).unwrap_or(dest);
+                out.push(Event::Start(Tag::Link(dest, title)));
+            },
+            Event::Start(Tag::Image(dest, title)) => {
+                let dest = asset_link(&dest, page_path, assets).map(Cow::Owned).unwrap_or(dest);
+                out.push(Event::Start(Tag::Image(dest, title)));
+            },
+            other => out.push(other),
+        }
+    }
+
+    Ok(out)
+}
@@ -12,11 +12,34 @@ extern crate walkdir;
 extern crate pulldown_cmark;
 extern crate time;
 extern crate toml;
+extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
 mod serve;
+mod menu;
+mod nav;
+mod series;
+mod related;
+mod summary;
+mod stats;
+mod extensions;
+mod toc;
+mod highlight;
+mod shortcodes;
+mod links;
+mod wiki;
+mod admonitions;
+mod math;
+mod emoji;
+mod citations;
+mod footnotes;
+mod external;
+mod glossary;
+mod sanitize;
+mod formats;
+mod notebook;
 
 use std::convert;
 use std::env;
@@ -26,7 +49,6 @@ use std::io::{Read, Write};
 use std::path::{Path, PathBuf, Component};
 use std::ffi::OsStr;
 use std::collections::{HashSet, HashMap};
-use pulldown_cmark as markdown;
 
 fn quilt_err<'a>(err: &'a str) -> ! {
     eprintln!("Error: {}", err);
@@ -79,102 +101,271 @@ fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<(), QuiltE
    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'  => out.push_str("&amp;"),
+            '<'  => out.push_str("&lt;"),
+            '>'  => out.push_str("&gt;"),
+            '"'  => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _    => out.push(c),
+        }
+    }
+    out
+}
+
+// Output URL of the page keyed by `page_path` (eg. site/blog/post => /blog/post.html)
+fn page_url(page_path: &Path) -> String {
+    let adjusted = page_path.strip_prefix("site").unwrap_or(page_path);
+    let mut url  = String::new();
+    for comp in adjusted.components() {
+        if let Component::Normal(ref s) = comp {
+            url.push('/');
+            url.push_str(&s.to_string_lossy());
+        }
+    }
+    url.push_str(".html");
+    url
+}
+
+// Replaces each {{key}} in a template with its value in a single pass, so placeholders that
+// appear inside the values themselves are left alone. Unknown keys are kept as they are.
+fn fill_template(wrap_str: &str, vars: &HashMap<String, String>) -> String {
+    let mut filled = String::with_capacity(wrap_str.len());
+    let mut rest   = wrap_str;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}").and_then(|end| vars.get(&after[..end]).map(|val| (end, val))) {
+            Some((end, val)) => {
+                filled.push_str(val);
+                rest = &after[end + 2..];
+            },
+            None => {
+                filled.push_str("{{");
+                rest = after;
+            },
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[derive(Debug, Default, Deserialize)]
 struct PageToml {
-    theme    : Option<String>,
-    template : Option<String>,
+    theme          : Option<String>,
+    template       : Option<String>,
+    title          : Option<String>,
+    weight         : Option<i64>,
+    menu           : Option<String>,
+    series         : Option<String>,
+    series_part    : Option<i64>,
+    #[serde(default)]
+    tags           : Vec<String>,
+    #[serde(default)]
+    categories     : Vec<String>,
+    summary        : Option<String>,
+    #[serde(default)]
+    markdown       : extensions::MarkdownToml,
+    bibliography   : Option<String>,
+    citation_style : Option<String>,
+    footnote_style : Option<String>,
+    render         : Option<String>,
 }
 
 impl PageToml {
     fn empty() -> Self {
-        PageToml {theme: None, template: None}
+        PageToml::default()
+    }
+}
+
+// themes/<theme>/shortcodes, for pages which request a theme
+fn shortcode_dir(themes_dir: &Option<PathBuf>, ptoml: &PageToml) -> Option<PathBuf> {
+    match (themes_dir, &ptoml.theme) {
+        (&Some(ref tpath), &Some(ref theme)) => Some(tpath.join(theme).join("shortcodes")),
+        _ => None,
     }
 }
 
+#[derive(Debug)]
+struct RenderOptions<'site> {
+    markdown         : extensions::MarkdownOptions,
+    words_per_minute : usize,
+    highlight        : ConfigHighlight,
+    shortcode_dir    : Option<PathBuf>,
+    site_root        : PathBuf,
+    page_path        : &'site Path,
+    page_urls        : &'site HashMap<PathBuf, String>,
+    page_assets      : &'site HashMap<PathBuf, String>,
+    wiki             : &'site wiki::WikiIndex,
+    bibliography     : Option<&'site citations::Bibliography>,
+    citation_style   : citations::Style,
+    footnote_style   : footnotes::FootnoteStyle,
+    external_links   : &'site ConfigExternalLinks,
+    glossary         : Option<&'site glossary::Glossary>,
+    sanitize         : Option<&'site ConfigSanitize>,
+}
+
+#[derive(Debug)]
+struct Rendered {
+    html  : String,
+    stats : stats::PageStats,
+    deps  : Vec<PathBuf>,
+    // Further output files (eg. images extracted from a notebook), relative to the build directory
+    files : Vec<(PathBuf, Vec<u8>)>,
+}
+
 #[derive(Debug)]
 struct Page {
     pub name       : String,
     pub section_id : usize ,
     pub page_toml  : PageToml,
     pub has_toml   : bool  ,
-    pub has_md     : bool  ,
+    // Extension of the content file, which picks its ContentFormat
+    pub content    : Option<String>,
 }
 
-impl Page {
-    fn generate<'buf>(&self, in_buf: & 'buf str, temp: Option<PathBuf>) -> Result<String, QuiltError> {
-            let wrap_str = {
-                if let Some(ref temp_path) = temp {
-                    let mut temp_buf = String::new();
-                    let mut tempf = fs::File::open(temp_path)?;
-                    tempf.read_to_string(&mut temp_buf)?;
-                    temp_buf
-                }
-                else {
-                     String::from("<html><body><article>{{content}}</article></body></html>")
-                }
-            };
+fn read_template(temp: &Option<PathBuf>) -> Result<String, QuiltError> {
+    if let Some(ref temp_path) = *temp {
+        let mut temp_buf = String::new();
+        let mut tempf = fs::File::open(temp_path)?;
+        tempf.read_to_string(&mut temp_buf)?;
+        Ok(temp_buf)
+    }
+    else {
+        Ok(String::from("<html><body><article>{{content}}</article></body></html>"))
+    }
+}
+
+// Fills the template's placeholders and puts `content` in place of {{content}}
+fn wrap_content(wrap_str: &str, content: &str, vars: &HashMap<String, String>,
+                temp: &Option<PathBuf>) -> Result<String, QuiltError> {
+    let mut out_buf = String::with_capacity(wrap_str.len() + content.len());
+
+    // Split before filling, so a {{content}} inside a value is not taken for the template's own
+    let wrap_parts = wrap_str.split("{{content}}").collect::<Vec<&str>>();
+    if wrap_parts.len() == 2 {
+        out_buf.push_str(&fill_template(wrap_parts[0], vars));
+        out_buf.push_str(content);
+        out_buf.push_str(&fill_template(wrap_parts[1], vars));
+        Ok(out_buf)
+    }
+    else {
+        let name = temp.as_ref().map(|t| t.display().to_string()).unwrap_or_default();
+        Err(QuiltError {source : "Generator".to_owned(),
+                        message: format!("Invalid Template {}: no {{content}}.", name) })
+    }
+}
 
+// Editor backups, swap files and hidden files, which are never part of the site
+fn is_scratch_file(name: &str) -> bool {
+    name.starts_with('.') || name.ends_with('~') || (name.starts_with('#') && name.ends_with('#'))
+        || [".swp", ".swo", ".bak", ".orig", ".tmp"].iter().any(|ext| name.ends_with(ext))
+}
 
-            let parser = markdown::Parser::new(&in_buf);
-            let mut parse_buf = String::new();
-            markdown::html::push_html(&mut parse_buf, parser);
-            
-            let mut exp_len = wrap_str.len();
-            exp_len        += parse_buf.len();
+// Placeholders which only some pages fill, left empty elsewhere (eg. on pages without a series,
+// or generated pages) rather than showing up in the output as {{...}}
+const PAGE_VARS : &'static [&'static str] = &[
+    "toc", "bibliography", "page.related", "page.summary", "page.description", "page.backlinks",
+    "page.word_count", "page.reading_time", "series.name", "series.part", "series.total", "series.list",
+    "series.prev", "series.next",
+];
+
+fn blank_page_vars(vars: &mut HashMap<String, String>) {
+    for name in PAGE_VARS {
+        vars.entry((*name).to_owned()).or_insert_with(String::new);
+    }
+}
 
-            let mut out_buf = String::with_capacity(exp_len);
-            
-            let wrap_parts = wrap_str.split("{{content}}").collect::<Vec<&str>>();
-            if wrap_parts.len() == 2 {
-                out_buf.push_str(wrap_parts[0]);
-                out_buf.push_str(&parse_buf);
-                out_buf.push_str(wrap_parts[1]);
-                Ok(out_buf)
-            }
-            else {
-                Err(QuiltError {source : "Generator".to_owned(),
-                                message: format!("Invalid Template {}: no {{content}}.", temp.unwrap().display()) })
-            }
+impl Page {
+    fn has_content(&self) -> bool {
+        self.content.is_some()
     }
 
 }
 
 #[derive(Debug)]
 struct Site {
-    site_dir   : PathBuf,
-    static_dir : Option<PathBuf>,
-    themes_dir : Option<PathBuf>,
-    sections   : Vec<PathBuf>,
-    pages      : HashMap<PathBuf, Page>,    
+    site_dir       : PathBuf,
+    static_dir     : Option<PathBuf>,
+    themes_dir     : Option<PathBuf>,
+    sections       : Vec<PathBuf>,
+    pages          : HashMap<PathBuf, Page>,    
+    menus          : HashMap<String, Vec<menu::MenuEntry>>,
+    series         : HashMap<String, Vec<series::SeriesEntry>>,
+    related        : HashMap<PathBuf, Vec<related::Related>>,
+    summaries      : HashMap<PathBuf, summary::Summary>,
+    urls           : HashMap<PathBuf, String>,
+    assets         : HashMap<PathBuf, String>,
+    wiki           : wiki::WikiIndex,
+    // Content files, read once for summaries, wiki links and rendering
+    sources        : HashMap<PathBuf, String>,
+    bibliographies : HashMap<PathBuf, citations::Bibliography>,
 }
 
 impl Site {
     fn init(from_path: PathBuf) -> Self {
         Site {
-            site_dir  : from_path.join("site"),
-            static_dir: None,
-            themes_dir: None,
-            sections  : vec![],
-            pages     : HashMap::new(),
+            site_dir      : from_path.join("site"),
+            static_dir    : None,
+            themes_dir    : None,
+            sections      : vec![],
+            pages         : HashMap::new(),
+            menus         : HashMap::new(),
+            series        : HashMap::new(),
+            related       : HashMap::new(),
+            summaries     : HashMap::new(),
+            urls          : HashMap::new(),
+            assets        : HashMap::new(),
+            wiki          : wiki::WikiIndex::default(),
+            sources       : HashMap::new(),
+            bibliographies: HashMap::new(),
         }
     }
+
+    // site/blog/post => <site_dir>/blog/post.md, with the extension of the page's content file
+    fn content_path(&self, page_path: &Path, page: &Page) -> PathBuf {
+        let mut content_path = self.site_dir.join(page_path.strip_prefix("site").unwrap());
+        content_path.set_extension(page.content.as_ref().map(|e| e.as_str()).unwrap_or(""));
+        content_path
+    }
+}
+
+// A page's bibliography is found like an include; the site-wide one is relative to the site root
+fn bibliography_path(config: &ConfigBibliography, site_root: &Path, page: &Page,
+                     content_path: &Path) -> Option<PathBuf> {
+    match (&page.page_toml.bibliography, &config.file) {
+        (&Some(ref file), _)     => Some(shortcodes::resolve_path(file, site_root, content_path)),
+        (&None, &Some(ref file)) => Some(site_root.join(file)),
+        (&None, &None)           => None,
+    }
 }
 
 #[derive(Debug)]
 struct Job<'args> {
     from_path : &'args str   ,
     to_path   : &'args str   ,
+    build     : &'args ConfigBuild,
+    config    : &'args Config,
+    verbose   : bool    ,
     site      : Site    ,
+    formats   : formats::Registry,
 }
 
 impl<'args> Job<'args> {
-    fn init(from_path: &'args str, to_path: &'args str) -> Self {
+    fn init(from_path: &'args str, build: &'args ConfigBuild, config: &'args Config, verbose: bool) -> Self {
         let site = Site::init(PathBuf::from(from_path));
 
         Job {
-            from_path: from_path,
-            to_path  : to_path  ,
-            site     : site     ,
+            from_path: from_path ,
+            to_path  : &build.out,
+            build    : build     ,
+            config   : config    ,
+            verbose  : verbose   ,
+            site     : site      ,
+            formats  : formats::Registry::new(),
         }
     }
 
@@ -186,6 +377,7 @@ impl<'args> Job<'args> {
         let mut has_static: bool = false;
         let mut has_site  : bool = false;
         let mut sections  : Vec<PathBuf>    = vec![cursec_path.clone()];
+        let mut assets    : Vec<PathBuf>    = vec![];
 
         let site_dir   = self.site.site_dir.clone();
         let themes_dir = PathBuf::from(self.from_path).join("themes");
@@ -199,7 +391,18 @@ impl<'args> Job<'args> {
             let mut await_context = false;
             let mut mapping = false;
             
-            for entry in walkdir::WalkDir::new(self.from_path) {
+            // Hidden directories, like the .ipynb_checkpoints Jupyter keeps beside notebooks, are not part of the site
+            let verbose = self.verbose;
+            let walker  = walkdir::WalkDir::new(self.from_path).into_iter().filter_entry(|entry| {
+                let hidden = entry.depth() > 0 && entry.file_type().is_dir()
+                                               && entry.file_name().to_string_lossy().starts_with('.');
+                if hidden && verbose {
+                    println!("....skipping {}", entry.path().display());
+                }
+                !hidden
+            });
+
+            for entry in walker {
                 let entry = entry?;
 
                 if entry.path() == from_dir {
@@ -259,11 +462,31 @@ impl<'args> Job<'args> {
                             }
                         }
 
-                        let ext = entry.path().extension().unwrap_or(OsStr::new(""));
+                        let ext = entry.path().extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("");
                         
                         if ext == "" {
                             continue
                         }
+
+                        if is_scratch_file(&entry.file_name().to_string_lossy()) {
+                            if self.verbose {
+                                println!("....skipping {}", entry.path().display());
+                            }
+                            continue
+                        }
+
+                        // Other files with a known extension are assets bundled with the pages beside them
+                        let is_content = self.formats.is_content(ext);
+                        if !is_content && ext != formats::SIDECAR {
+                            if !self.config.assets.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)) {
+                                return Err(QuiltError {source : "Asset".to_owned(),
+                                                       message: format!("{} is not a page, sidecar or asset file \
+                                                                         (asset extensions are set in [assets])",
+                                                                        entry.path().display())});
+                            }
+                            assets.push((*entry.path().strip_prefix(&from_dir).unwrap()).to_path_buf());
+                            continue
+                        }
                         
                         let page_path = cursec_path.join(name);
                         let mut page : &mut Page;
@@ -271,39 +494,31 @@ impl<'args> Job<'args> {
                         if pages.contains_key(&page_path) {
                             page = pages.get_mut(&page_path).unwrap();
                             
-                            if page.has_md {
-                               quilt_assert(ext == "toml",
+                            if page.has_content() {
+                               quilt_assert(ext == formats::SIDECAR,
                                             &format!("Unexpected File: {:?}", entry.path()));
                                page.has_toml = true;
 
                            }
                            else if page.has_toml {
-                               quilt_assert(ext == "md",
+                               quilt_assert(is_content,
                                             &format!("Unexpected File: {:?}", entry.path()));
-                               page.has_md = true;
+                               page.content = Some(ext.to_owned());
                            }
                         }
                         else {
-                            let (is_md, is_toml) = (ext == "md", ext == "toml");
-
-                            if is_md || is_toml {
-                                let new_page  = Page {name: name.to_str().unwrap().to_owned(),
-                                                      section_id: cursec_id,
-                                                      page_toml : PageToml::empty(),
-                                                      has_md    : is_md    , 
-                                                      has_toml  : is_toml  ,         };
-                                {
-                                    pages.insert(page_path.clone(), new_page);
-                                }
-                                page = pages.get_mut(&page_path).unwrap();
-                            }    
-                            else {
-
-                                quilt_err(&format!("{} is not a valid pagefile.", entry.path().display()));
+                            let new_page  = Page {name: name.to_str().unwrap().to_owned(),
+                                                  section_id: cursec_id,
+                                                  page_toml : PageToml::empty(),
+                                                  content   : if is_content { Some(ext.to_owned()) } else { None },
+                                                  has_toml  : !is_content,       };
+                            {
+                                pages.insert(page_path.clone(), new_page);
                             }
+                            page = pages.get_mut(&page_path).unwrap();
                         }
 
-                        if ext == "toml" {
+                        if ext == formats::SIDECAR {
                             let mut toml_buf = String::new();
                             let mut toml_f   = fs::File::open(entry.path())?;
                             toml_f.read_to_string(&mut toml_buf)?;
@@ -327,15 +542,48 @@ impl<'args> Job<'args> {
         let static_opt = if has_static { Some(static_dir) } else { None };
         let themes_opt = if has_themes { Some(themes_dir) } else { None };
 
+        let markdown_opts = self.markdown_options();
         let site = &mut self.site;
         
         site.static_dir = static_opt;
         site.themes_dir = themes_opt;
         site.sections   = sections;
+        site.menus      = menu::resolve(&self.config.menu, &site.pages)?;
+        site.series     = series::collect(&site.pages)?;
+        site.related    = related::compute(&self.config.related, &site.pages);
+        site.urls       = links::page_urls(&site.pages);
+        site.assets     = links::asset_urls(&assets);
+
+        let site_root = Path::new(self.from_path);
+        for (path, page) in &site.pages {
+            if !page.has_content() {
+                continue;
+            }
+            let content_path = site.content_path(path, page);
+            let mut content_buf = String::new();
+            fs::File::open(&content_path)?.read_to_string(&mut content_buf)?;
+            site.sources.insert(path.clone(), content_buf);
+
+            if let Some(bib_path) = bibliography_path(&self.config.bibliography, site_root, page, &content_path) {
+                if !site.bibliographies.contains_key(&bib_path) {
+                    let bib = citations::load(&bib_path)?;
+                    site.bibliographies.insert(bib_path, bib);
+                }
+            }
+        }
+
+        site.wiki       = wiki::WikiIndex::build(site, &self.formats, &markdown_opts)?;
+        site.summaries  = summary::collect(self.config, &self.formats, &markdown_opts, site_root, site)?;
         
         Ok(())
     }
 
+    // Markdown options for the whole build, before any per-page overrides
+    fn markdown_options(&self) -> extensions::MarkdownOptions {
+        extensions::MarkdownOptions::default().merge(&self.config.markdown)
+                                              .merge(&self.build.markdown)
+    }
+
     fn build(&mut self) -> Result<(), QuiltError> {
         let build_dir = PathBuf::from(self.to_path);
 
@@ -392,16 +640,51 @@ impl<'args> Job<'args> {
             let adj = section.strip_prefix("site").unwrap();
             fs::create_dir_all(build_dir.join(adj))?;
 
-            if let Some(Component::Normal(ref s)) = adj.components().next() {
-                qf_lines.push(s.to_str().unwrap().to_owned());
+            // Every section directory is recorded, so nested ones (like page bundles) are removed before their parents
+            if adj.components().next().is_some() {
+                qf_lines.push(adj.to_str().unwrap().to_owned());
             }
         }
 
+        if self.config.highlight.enabled && !highlight::is_theme(&self.config.highlight.theme) {
+            return Err(QuiltError {source : "Highlight".to_owned(),
+                                   message: format!("Unknown highlight theme {}", self.config.highlight.theme)});
+        }
+
+        let markdown_opts = self.markdown_options();
+        if self.verbose {
+            println!("....markdown extensions: [{}]", markdown_opts.enabled().join(", "));
+        }
+
+        let mut tree = nav::build_tree(&site.sections, &site.pages, &site.summaries);
+        let mut page_stats : HashMap<String, stats::PageStats> = HashMap::new();
+
         let mut found_themes : HashMap<String, HashSet<String>> = HashMap::new();
 
+        // Bundled assets are copied to the same place relative to their pages
+        let mut assets : Vec<&PathBuf> = site.assets.keys().collect();
+        assets.sort();
+        for asset in assets {
+            let adjusted = asset.strip_prefix("site").unwrap();
+            let out_path = build_dir.join(adjusted);
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(PathBuf::from(self.from_path).join(asset), &out_path)?;
+            qf_lines.push(adjusted.to_str().unwrap().to_owned());
+        }
+
+        let glossary = match self.config.glossary.file {
+            Some(ref file) => {
+                let url = self.config.glossary.page.as_ref().map(|p| page_url(&Path::new("site").join(p)));
+                Some(glossary::load(&PathBuf::from(self.from_path).join(file), url)?)
+            },
+            None => None,
+        };
+
         for (path, page) in &site.pages {
-             if !page.has_md {
-                 eprintln!("Page {} ({}) does not have an associated markdown file - skipping.", page.name, path.display());
+             if !page.has_content() {
+                 eprintln!("Page {} ({}) does not have an associated content file - skipping.", page.name, path.display());
                  continue;
              }
 
@@ -434,26 +717,117 @@ impl<'args> Job<'args> {
              }
 
              let adjusted_path = path.strip_prefix("site").unwrap().to_path_buf();
+             let content_path  = site.content_path(path, page);
+             let ext           = page.content.as_ref().unwrap();
 
-             let mut md_path = site.site_dir.join(&adjusted_path);
-             md_path.set_extension("md");
-             
-             let mut page_md = fs::File::open(md_path)?;
-             
-             let mut md_buf = String::new();
-             page_md.read_to_string(&mut md_buf);
-             let html_buf = page.generate(&md_buf, theme_path)?;
+             let mut vars : HashMap<String, String> = HashMap::new();
+             for (name, entries) in &site.menus {
+                 vars.insert(format!("menu.{}", name), menu::render(entries, path));
+             }
+             vars.insert("site.tree".to_owned(), nav::render(&tree, path));
+             series::vars(&site.series, page, path, &mut vars);
+             vars.insert("page.related".to_owned(), related::render(&site.related[path]));
+             vars.insert("page.summary".to_owned(), site.summaries[path].html.clone());
+             vars.insert("page.description".to_owned(), site.summaries[path].text.clone());
+             vars.insert("page.backlinks".to_owned(), site.wiki.render_backlinks(path, &site.urls));
+
+             let site_root = PathBuf::from(self.from_path);
+             let bib_path  = bibliography_path(&self.config.bibliography, &site_root, page, &content_path);
+             let style = page.page_toml.citation_style.as_ref().unwrap_or(&self.config.bibliography.style);
+
+             let opts = RenderOptions {
+                 markdown         : markdown_opts.merge(&page.page_toml.markdown),
+                 words_per_minute : self.config.reading.words_per_minute,
+                 highlight        : self.config.highlight.clone(),
+                 shortcode_dir    : shortcode_dir(&site.themes_dir, &page.page_toml),
+                 site_root        : site_root.clone(),
+                 page_path        : path,
+                 page_urls        : &site.urls,
+                 page_assets      : &site.assets,
+                 wiki             : &site.wiki,
+                 bibliography     : bib_path.as_ref().map(|p| &site.bibliographies[p]),
+                 citation_style   : citations::Style::parse(style)?,
+                 footnote_style   : footnotes::FootnoteStyle::parse(page.page_toml.footnote_style.as_ref()
+                                                                        .map(|s| s.as_str()).unwrap_or("endnotes"))?,
+                 external_links   : &self.config.external_links,
+                 glossary         : glossary.as_ref(),
+                 sanitize         : self.config.sanitize_policy(path),
+             };
+             if self.verbose && opts.markdown != markdown_opts {
+                 println!("....{} markdown extensions: [{}]", path.display(), opts.markdown.enabled().join(", "));
+             }
+
+             let format   = self.formats.get(ext).unwrap();
+             let rendered = format.render(page, &site.sources[path], &content_path, theme_path, &mut vars, &opts)?;
+             page_stats.insert(page_url(path), rendered.stats);
 
              let mut html_path = build_dir.join(adjusted_path);
              html_path.set_extension("html");
-             qf_lines.push(html_path.strip_prefix(&build_dir)
-                                    .unwrap()
-                                    .to_str()
-                                    .unwrap()
-                                    .to_owned());
+             let html_rel = html_path.strip_prefix(&build_dir)
+                                     .unwrap()
+                                     .to_str()
+                                     .unwrap()
+                                     .to_owned();
+
+             // Every build renders every page, so included and embedded files are only reported
+             if self.verbose {
+                 for dep in &rendered.deps {
+                     println!("....{} depends on {}", path.display(), dep.display());
+                 }
+             }
+             for &(ref file, ref data) in &rendered.files {
+                 fs::File::create(build_dir.join(file))?.write_all(data)?;
+                 qf_lines.push(file.to_str().unwrap().to_owned());
+             }
+             qf_lines.push(html_rel);
              
              let mut page_html = fs::File::create(html_path)?;
-             page_html.write_all(&html_buf.as_bytes())?;
+             page_html.write_all(&rendered.html.as_bytes())?;
+        }
+
+        if let (Some(ref glossary), Some(ref page)) = (glossary, &self.config.glossary.page) {
+            let key = Path::new("site").join(page);
+            if site.pages.contains_key(&key) {
+                return Err(QuiltError {source : "Glossary".to_owned(),
+                                       message: format!("The glossary page would replace {}", key.display())});
+            }
+
+            let temp_path = match (&site.themes_dir, &self.config.glossary.theme, &self.config.glossary.template) {
+                (&Some(ref tpath), &Some(ref theme), &Some(ref temp)) => {
+                    found_themes.entry(theme.to_owned()).or_insert_with(HashSet::new).insert(temp.to_owned());
+                    Some(tpath.join(theme).join(format!("{}.html", temp)))
+                },
+                _ => None,
+            };
+            let wrap_str = read_template(&temp_path)?;
+
+            let mut vars : HashMap<String, String> = HashMap::new();
+            for (name, entries) in &site.menus {
+                vars.insert(format!("menu.{}", name), menu::render(entries, &key));
+            }
+            vars.insert("site.tree".to_owned(), nav::render(&tree, &key));
+            blank_page_vars(&mut vars);
+
+            let html = wrap_content(&wrap_str, &glossary.render_page(), &vars, &temp_path)?;
+            let mut html_path = build_dir.join(page);
+            html_path.set_extension("html");
+            if let Some(parent) = html_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::File::create(&html_path)?.write_all(html.as_bytes())?;
+            qf_lines.push(format!("{}.html", page));
+        }
+
+        if self.build.nav_json {
+            nav::set_stats(&mut tree, &page_stats);
+            let json_buf = match serde_json::to_string_pretty(&tree) {
+                Ok(json) => json,
+                Err(err) => return Err(QuiltError {source : "Json".to_owned(),
+                                                   message: format!("Could not encode nav.json: {}", err)}),
+            };
+            let mut nav_f = fs::File::create(build_dir.join("nav.json"))?;
+            nav_f.write_all(json_buf.as_bytes())?;
+            qf_lines.push("nav.json".to_owned());
         }
 
         let tmp_dir    = build_dir.join(".quilt_tmp");
@@ -465,6 +839,11 @@ impl<'args> Job<'args> {
             fs::create_dir(&tmp_static)?;
         }
 
+        if self.config.highlight.enabled {
+            let mut css_f = fs::File::create(tmp_static.join("highlight.css"))?;
+            css_f.write_all(highlight::css(&self.config.highlight.theme).as_bytes())?;
+        }
+
         if let Some(ref theme_path) = site.themes_dir {
             let themes_data = tmp_static.join("themes");
             fs::create_dir(&themes_data);
@@ -514,11 +893,185 @@ struct ConfigBuild {
     default: bool,
     name: String,
     out: String,
+    #[serde(default = "false_val")]
+    nav_json: bool,
+    #[serde(default)]
+    markdown: extensions::MarkdownToml,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ConfigMenuItem {
+    name   : Option<String>,
+    url    : Option<String>,
+    page   : Option<String>,
+    #[serde(default)]
+    weight : i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigRelated {
+    limit             : usize,
+    tags_weight       : i64,
+    categories_weight : i64,
+}
+
+impl Default for ConfigRelated {
+    fn default() -> Self {
+        ConfigRelated {limit: 5, tags_weight: 1, categories_weight: 2}
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigSummary {
+    words : usize,
+}
+
+impl Default for ConfigSummary {
+    fn default() -> Self {
+        ConfigSummary {words: 70}
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigReading {
+    words_per_minute : usize,
+}
+
+impl Default for ConfigReading {
+    fn default() -> Self {
+        ConfigReading {words_per_minute: 200}
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+struct ConfigHighlight {
+    enabled      : bool,
+    theme        : String,
+    line_numbers : bool,
+}
+
+impl Default for ConfigHighlight {
+    fn default() -> Self {
+        ConfigHighlight {enabled: false, theme: "light".to_owned(), line_numbers: false}
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigBibliography {
+    file  : Option<String>,
+    style : String,
+}
+
+impl Default for ConfigBibliography {
+    fn default() -> Self {
+        ConfigBibliography {file: None, style: "author-date".to_owned()}
+    }
+}
+
+// Links to other sites, as found in rendered page content
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ConfigExternalLinks {
+    class           : Option<String>,
+    new_tab         : bool,
+    rel             : Option<String>,
+    allowed_domains : Vec<String>,
+}
+
+// `page` names a generated glossary page (eg. "glossary" for /glossary.html) which terms link to
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ConfigGlossary {
+    file     : Option<String>,
+    page     : Option<String>,
+    theme    : Option<String>,
+    template : Option<String>,
+}
+
+// Options for a section, keyed by its path under site/ ("" for the top level)
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ConfigSection {
+    sanitize : bool,
+}
+
+// What survives sanitisation in sections with `sanitize = true`
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigSanitize {
+    allowed_tags       : Vec<String>,
+    allowed_attributes : Vec<String>,
+    allowed_schemes    : Vec<String>,
+}
+
+impl Default for ConfigSanitize {
+    fn default() -> Self {
+        ConfigSanitize {allowed_tags      : sanitize::DEFAULT_TAGS.iter().map(|t| (*t).to_owned()).collect(),
+                        allowed_attributes: sanitize::DEFAULT_ATTRIBUTES.iter().map(|a| (*a).to_owned()).collect(),
+                        allowed_schemes   : sanitize::DEFAULT_SCHEMES.iter().map(|s| (*s).to_owned()).collect()}
+    }
+}
+
+// Files beside pages in site/ are copied to the output when their extension is listed here
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct ConfigAssets {
+    extensions : Vec<String>,
+}
+
+impl Default for ConfigAssets {
+    fn default() -> Self {
+        ConfigAssets {extensions: links::DEFAULT_ASSET_EXTENSIONS.iter().map(|e| (*e).to_owned()).collect()}
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct Config {
-    build : Vec<ConfigBuild>,
+    build          : Vec<ConfigBuild>,
+    #[serde(default)]
+    menu           : HashMap<String, Vec<ConfigMenuItem>>,
+    #[serde(default)]
+    related        : ConfigRelated,
+    #[serde(default)]
+    summary        : ConfigSummary,
+    #[serde(default)]
+    reading        : ConfigReading,
+    #[serde(default)]
+    markdown       : extensions::MarkdownToml,
+    #[serde(default)]
+    highlight      : ConfigHighlight,
+    #[serde(default)]
+    bibliography   : ConfigBibliography,
+    #[serde(default)]
+    external_links : ConfigExternalLinks,
+    #[serde(default)]
+    glossary       : ConfigGlossary,
+    #[serde(default)]
+    sections       : HashMap<String, ConfigSection>,
+    #[serde(default)]
+    sanitize       : ConfigSanitize,
+    #[serde(default)]
+    assets         : ConfigAssets,
+}
+
+impl Config {
+    // The sanitisation policy for a page in a section marked `sanitize = true`; the most specific section wins
+    fn sanitize_policy(&self, page_path: &Path) -> Option<&ConfigSanitize> {
+        let mut section = page_path.parent();
+        while let Some(sec) = section {
+            let key = sec.strip_prefix("site").unwrap_or(sec).to_string_lossy().into_owned();
+            if let Some(config) = self.sections.get(&key) {
+                return if config.sanitize { Some(&self.sanitize) } else { None };
+            }
+            section = sec.parent();
+        }
+        None
+    }
 }
 
 fn get_build(config: &Config, build_name: Option<&String>) -> ConfigBuild {
@@ -548,7 +1101,7 @@ fn get_build(config: &Config, build_name: Option<&String>) -> ConfigBuild {
     build.unwrap()
 }
 
-fn build(config: &Config, build_name: Option<&String>) {
+fn build(config: &Config, build_name: Option<&String>, verbose: bool) {
     let build = get_build(config, build_name); 
 
     let from: &str = "./";
@@ -556,7 +1109,7 @@ fn build(config: &Config, build_name: Option<&String>) {
     
     println!("Initiating build: {} => {}", from, to);
 
-    let mut job = Job::init(from, to);
+    let mut job = Job::init(from, &build, config, verbose);
     
     println!("....composing site");
     match job.compose() {
@@ -598,8 +1151,37 @@ fn get_config(args: &Vec<String>) -> Result<Config, QuiltError> {
 
 }
 
+// Splits the command line into positional arguments and the verbose flag. Anything after `--` is
+// positional, so that a path starting with `-` can still be given.
+fn parse_args<I: Iterator<Item=String>>(all_args: I) -> Result<(Vec<String>, bool), QuiltError> {
+    let mut args    = vec![];
+    let mut verbose = false;
+    let mut options = true;
+
+    for arg in all_args {
+        if options && arg == "--" {
+            options = false;
+        }
+        else if options && (arg == "--verbose" || arg == "-v") {
+            verbose = true;
+        }
+        else if options && arg.starts_with('-') && arg != "-" {
+            return Err(QuiltError {source : "Args".to_owned(),
+                                   message: format!("Unrecognised option {}", arg)});
+        }
+        else {
+            args.push(arg);
+        }
+    }
+
+    Ok((args, verbose))
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (args, verbose) = match parse_args(env::args()) {
+        Ok(parsed) => parsed,
+        Err(e)     => quilt_err(&format!("[Init] {}", e.message)),
+    };
 
     if args.len() < 2 {
         println!("Commands: build, serve")
@@ -613,7 +1195,7 @@ fn main() {
                  
                  };
                  
-                 build(&config, args.get(2)); 
+                 build(&config, args.get(2), verbose); 
             },
 
             "serve" => {
@@ -634,3 +1216,66 @@ fn main() {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A site written under the system temp directory, removed again when dropped
+    pub struct TempSite {
+        pub root : PathBuf,
+    }
+
+    impl TempSite {
+        pub fn new(name: &str, files: &[(&str, &str)]) -> TempSite {
+            let root = env::temp_dir().join(format!("quilt-{}-{}", name, std::process::id()));
+            for &(path, content) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+            }
+            TempSite {root: root}
+        }
+    }
+
+    impl Drop for TempSite {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn placeholders_in_values_are_not_expanded() {
+        let mut vars = HashMap::new();
+        vars.insert("title".to_owned(), "Using {{summary}} and {{content}}".to_owned());
+        vars.insert("summary".to_owned(), "{{title}}".to_owned());
+        let filled = fill_template("<h1>{{title}}</h1><p>{{summary}}</p>{{unknown}}", &vars);
+        assert_eq!(filled, "<h1>Using {{summary}} and {{content}}</h1><p>{{title}}</p>{{unknown}}");
+
+        match wrap_content("<title>{{title}}</title>{{content}}", "body", &vars, &None) {
+            Ok(page) => assert_eq!(page, "<title>Using {{summary}} and {{content}}</title>body"),
+            Err(e)   => panic!("{}", e.message),
+        }
+    }
+
+    const NOTEBOOK : &'static str = r##"{"nbformat": 4, "metadata": {}, "cells": [
+        {"cell_type": "markdown", "source": ["# Analysis"]}
+    ]}"##;
+
+    #[test]
+    fn compose_skips_hidden_directories() {
+        let site = TempSite::new("hidden", &[
+            ("site/data/analysis.ipynb", NOTEBOOK),
+            ("site/data/.ipynb_checkpoints/analysis-checkpoint.ipynb", NOTEBOOK),
+            ("site/.drafts/post.md", "# Draft"),
+        ]);
+        let config : Config = toml::from_str("[[build]]\nname = \"test\"\nout = \"out\"").unwrap();
+        let mut job = Job::init(site.root.to_str().unwrap(), &config.build[0], &config, false);
+        if let Err(e) = job.compose() {
+            panic!("{}: {}", e.source, e.message);
+        }
+
+        let pages : Vec<&PathBuf> = job.site.pages.keys().collect();
+        assert_eq!(pages, vec![&PathBuf::from("site/data/analysis")]);
+        assert_eq!(job.site.sections, vec![PathBuf::from("site"), PathBuf::from("site/data")]);
+    }
+}
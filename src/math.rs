@@ -0,0 +1,516 @@
+// A TeX subset to MathML translator for $inline$ and $$display$$ math
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Letter(char),
+    Number(String),
+    Symbol(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+}
+
+fn tokenise(tex: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars  = tex.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if n.is_alphabetic() {
+                        name.push(n);
+                        chars.next();
+                    }
+                    else {
+                        break;
+                    }
+                }
+                if name.is_empty() {
+                    if let Some(n) = chars.next() {
+                        name.push(n);
+                    }
+                }
+                tokens.push(Token::Command(name));
+            },
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '^' => tokens.push(Token::Sup),
+            '_' => tokens.push(Token::Sub),
+            c if c.is_whitespace() => (),
+            c if c.is_ascii_digit() => {
+                let mut num = c.to_string();
+                while let Some(&n) = chars.peek() {
+                    if n.is_ascii_digit() || n == '.' {
+                        num.push(n);
+                        chars.next();
+                    }
+                    else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(num));
+            },
+            c if c.is_alphabetic() => tokens.push(Token::Letter(c)),
+            c => tokens.push(Token::Symbol(c)),
+        }
+    }
+    tokens
+}
+
+// Escapes everything but letters and digits as character references, so that
+// markdown leaves the markup alone
+fn text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_alphanumeric() && c.is_ascii() || c == ' ' {
+            out.push(c);
+        }
+        else {
+            out.push_str(&format!("&#{};", c as u32));
+        }
+    }
+    out
+}
+
+fn identifier(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α", "beta" => "β", "gamma" => "γ", "delta" => "δ", "epsilon" => "ϵ",
+        "varepsilon" => "ε", "zeta" => "ζ", "eta" => "η", "theta" => "θ", "vartheta" => "ϑ",
+        "iota" => "ι", "kappa" => "κ", "lambda" => "λ", "mu" => "μ", "nu" => "ν", "xi" => "ξ",
+        "pi" => "π", "varpi" => "ϖ", "rho" => "ρ", "sigma" => "σ", "tau" => "τ", "upsilon" => "υ",
+        "phi" => "ϕ", "varphi" => "φ", "chi" => "χ", "psi" => "ψ", "omega" => "ω",
+        "Gamma" => "Γ", "Delta" => "Δ", "Theta" => "Θ", "Lambda" => "Λ", "Xi" => "Ξ", "Pi" => "Π",
+        "Sigma" => "Σ", "Upsilon" => "Υ", "Phi" => "Φ", "Psi" => "Ψ", "Omega" => "Ω",
+        "infty" => "∞", "partial" => "∂", "nabla" => "∇", "ell" => "ℓ", "hbar" => "ℏ",
+        "emptyset" => "∅", "aleph" => "ℵ",
+        _ => return None,
+    })
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑", "prod" => "∏", "int" => "∫", "iint" => "∬", "oint" => "∮",
+        "bigcup" => "⋃", "bigcap" => "⋂",
+        "cdot" => "⋅", "times" => "×", "div" => "÷", "pm" => "±", "mp" => "∓", "ast" => "∗",
+        "circ" => "∘", "bullet" => "∙", "oplus" => "⊕", "otimes" => "⊗",
+        "leq" => "≤", "le" => "≤", "geq" => "≥", "ge" => "≥", "neq" => "≠", "ne" => "≠",
+        "approx" => "≈", "equiv" => "≡", "sim" => "∼", "simeq" => "≃", "cong" => "≅", "propto" => "∝",
+        "ll" => "≪", "gg" => "≫",
+        "in" => "∈", "notin" => "∉", "ni" => "∋", "subset" => "⊂", "subseteq" => "⊆", "supset" => "⊃",
+        "supseteq" => "⊇", "cup" => "∪", "cap" => "∩", "setminus" => "∖",
+        "forall" => "∀", "exists" => "∃", "neg" => "¬", "lnot" => "¬", "land" => "∧", "wedge" => "∧",
+        "lor" => "∨", "vee" => "∨",
+        "to" => "→", "rightarrow" => "→", "leftarrow" => "←", "gets" => "←", "mapsto" => "↦",
+        "Rightarrow" => "⇒", "Leftarrow" => "⇐", "implies" => "⟹", "iff" => "⟺",
+        "leftrightarrow" => "↔", "Leftrightarrow" => "⇔",
+        "ldots" => "…", "cdots" => "⋯", "vdots" => "⋮", "ddots" => "⋱",
+        "langle" => "⟨", "rangle" => "⟩", "lfloor" => "⌊", "rfloor" => "⌋", "lceil" => "⌈",
+        "rceil" => "⌉", "mid" => "∣", "parallel" => "∥", "perp" => "⊥",
+        "{" => "{", "}" => "}", "|" => "‖", "," => "\u{2009}", ";" => "\u{2005}", "!" => "", " " => " ",
+        "quad" => "\u{2003}", "qquad" => "\u{2003}\u{2003}",
+        _ => return None,
+    })
+}
+
+fn function(name: &str) -> bool {
+    ["sin", "cos", "tan", "cot", "sec", "csc", "sinh", "cosh", "tanh", "arcsin", "arccos", "arctan",
+     "log", "ln", "exp", "lim", "max", "min", "sup", "inf", "det", "dim", "ker", "gcd", "deg", "arg",
+     "Pr"].contains(&name)
+}
+
+// Operators whose limits sit above and below in display mode
+fn has_limits(name: &str) -> bool {
+    ["sum", "prod", "bigcup", "bigcap", "lim", "max", "min", "sup", "inf"].contains(&name)
+}
+
+fn accent(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "hat" => "^", "widehat" => "^", "bar" => "¯", "overline" => "¯", "vec" => "→",
+        "tilde" => "~", "widetilde" => "~", "dot" => "˙", "ddot" => "¨",
+        _ => return None,
+    })
+}
+
+fn variant(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathbf" => "bold", "mathit" => "italic", "mathrm" => "normal", "mathbb" => "double-struck",
+        "mathcal" => "script", "mathfrak" => "fraktur", "mathsf" => "sans-serif", "mathtt" => "monospace",
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens  : Vec<Token>,
+    pos     : usize,
+    display : bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    // A sequence of items, up to a closing brace, \right, ] (when `bracket`) or the end
+    fn expr(&mut self, bracket: bool) -> String {
+        let mut items = String::new();
+        loop {
+            match self.peek() {
+                None | Some(&Token::Close) => break,
+                Some(&Token::Command(ref c)) if c == "right" => break,
+                Some(&Token::Symbol(']')) if bracket => break,
+                _ => (),
+            }
+            items.push_str(&self.item());
+        }
+        items
+    }
+
+    fn item(&mut self) -> String {
+        let (base, limits) = self.atom();
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(&Token::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.atom().0);
+                },
+                Some(&Token::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.atom().0);
+                },
+                Some(&Token::Symbol('\'')) if sup.is_none() => {
+                    self.next();
+                    sup = Some(format!("<mo>{}</mo>", text("′")));
+                },
+                _ => break,
+            }
+        }
+
+        let (under, over, both) = if limits && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        match (sub, sup) {
+            (None, None)           => base,
+            (Some(b), None)        => format!("<{0}>{1}{2}</{0}>", under, base, b),
+            (None, Some(p))        => format!("<{0}>{1}{2}</{0}>", over, base, p),
+            (Some(b), Some(p))     => format!("<{0}>{1}{2}{3}</{0}>", both, base, b, p),
+        }
+    }
+
+    // A single element, and whether it takes its scripts as limits
+    fn atom(&mut self) -> (String, bool) {
+        let token = match self.next() {
+            Some(token) => token,
+            None        => return ("<mrow></mrow>".to_owned(), false),
+        };
+
+        let atom = match token {
+            Token::Letter(c)  => format!("<mi>{}</mi>", text(&c.to_string())),
+            Token::Number(n)  => format!("<mn>{}</mn>", text(&n)),
+            Token::Symbol('-') => format!("<mo>{}</mo>", text("−")),
+            Token::Symbol('\'') => format!("<mo>{}</mo>", text("′")),
+            Token::Symbol(c)  => format!("<mo>{}</mo>", text(&c.to_string())),
+            Token::Open => {
+                let inner = self.expr(false);
+                if let Some(&Token::Close) = self.peek() {
+                    self.next();
+                }
+                format!("<mrow>{}</mrow>", inner)
+            },
+            Token::Close | Token::Sup | Token::Sub => "<mrow></mrow>".to_owned(),
+            Token::Command(name) => return self.command(&name),
+        };
+        (atom, false)
+    }
+
+    // The raw characters of a {...} group, for \text
+    fn raw_group(&mut self) -> String {
+        let mut raw = String::new();
+        if let Some(&Token::Open) = self.peek() {
+            self.next();
+            let mut depth = 0;
+            while let Some(token) = self.next() {
+                match token {
+                    Token::Open => { depth += 1; raw.push('{'); },
+                    Token::Close if depth == 0 => break,
+                    Token::Close => { depth -= 1; raw.push('}'); },
+                    Token::Letter(c) | Token::Symbol(c) => raw.push(c),
+                    Token::Number(n) => raw.push_str(&n),
+                    Token::Command(c) => { raw.push('\\'); raw.push_str(&c); },
+                    Token::Sup => raw.push('^'),
+                    Token::Sub => raw.push('_'),
+                }
+                // Whitespace is dropped by the tokeniser; keep words apart
+                if let Some(&Token::Letter(_)) = self.peek() {
+                    if let Some(&Token::Letter(_)) = self.tokens.get(self.pos.wrapping_sub(1)) {
+                        continue;
+                    }
+                    raw.push(' ');
+                }
+            }
+        }
+        raw.trim().to_owned()
+    }
+
+    fn delimiter(&mut self) -> String {
+        match self.next() {
+            Some(Token::Symbol('.')) => String::new(),
+            Some(Token::Symbol(c))   => format!("<mo stretchy=\"true\">{}</mo>", text(&c.to_string())),
+            Some(Token::Command(ref c)) if operator(c).is_some() => {
+                format!("<mo stretchy=\"true\">{}</mo>", text(operator(c).unwrap()))
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn command(&mut self, name: &str) -> (String, bool) {
+        if let Some(ident) = identifier(name) {
+            return (format!("<mi>{}</mi>", text(ident)), false);
+        }
+        if let Some(op) = operator(name) {
+            return (format!("<mo>{}</mo>", text(op)), has_limits(name));
+        }
+        if function(name) {
+            return (format!("<mi>{}</mi>", text(name)), has_limits(name));
+        }
+        if let Some(mark) = accent(name) {
+            let (base, _) = self.atom();
+            return (format!("<mover accent=\"true\">{}<mo>{}</mo></mover>", base, text(mark)), false);
+        }
+        if let Some(var) = variant(name) {
+            let (inner, _) = self.atom();
+            return (format!("<mstyle mathvariant=\"{}\">{}</mstyle>", var, inner), false);
+        }
+
+        let element = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let (num, _) = self.atom();
+                let (den, _) = self.atom();
+                format!("<mfrac>{}{}</mfrac>", num, den)
+            },
+            "binom" => {
+                let (top, _) = self.atom();
+                let (bot, _) = self.atom();
+                format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>", top, bot)
+            },
+            "sqrt" => {
+                if let Some(&Token::Symbol('[')) = self.peek() {
+                    self.next();
+                    let index = self.expr(true);
+                    if let Some(&Token::Symbol(']')) = self.peek() {
+                        self.next();
+                    }
+                    let (base, _) = self.atom();
+                    format!("<mroot>{}<mrow>{}</mrow></mroot>", base, index)
+                }
+                else {
+                    let (base, _) = self.atom();
+                    format!("<msqrt>{}</msqrt>", base)
+                }
+            },
+            "text" | "textrm" | "mbox" | "operatorname" => {
+                let raw = self.raw_group();
+                if name == "operatorname" {
+                    format!("<mi>{}</mi>", text(&raw))
+                }
+                else {
+                    format!("<mtext>{}</mtext>", text(&raw))
+                }
+            },
+            "left" => {
+                let open  = self.delimiter();
+                let inner = self.expr(false);
+                let close = match self.peek() {
+                    Some(&Token::Command(ref c)) if c == "right" => true,
+                    _ => false,
+                };
+                let close = if close {
+                    self.next();
+                    self.delimiter()
+                } else {
+                    String::new()
+                };
+                format!("<mrow>{}{}{}</mrow>", open, inner, close)
+            },
+            "\\" => "<mspace linebreak=\"newline\"/>".to_owned(),
+            _ => format!("<merror><mtext>{}</mtext></merror>", text(&format!("\\{}", name))),
+        };
+        (element, false)
+    }
+}
+
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {tokens: tokenise(tex), pos: 0, display: display};
+
+    let mut body = String::new();
+    while parser.peek().is_some() {
+        body.push_str(&parser.expr(false));
+        // Stray closing braces and \right are skipped rather than ending the formula
+        parser.next();
+    }
+
+    let annotation = format!("<annotation encoding=\"application/x-tex\">{}</annotation>", text(tex.trim()));
+    if display {
+        format!("<math display=\"block\"><semantics><mrow>{}</mrow>{}</semantics></math>", body, annotation)
+    }
+    else {
+        format!("<math><semantics><mrow>{}</mrow>{}</semantics></math>", body, annotation)
+    }
+}
+
+// Length of the run of backticks `text` starts with
+pub fn backtick_run(text: &str) -> usize {
+    text.len() - text.trim_left_matches('`').len()
+}
+
+// Replaces $inline$ and $$display$$ math outside code with MathML. A $ only opens inline math
+// when followed by a non-space, and only closes it when preceded by one and not followed by a
+// digit, so prices like $5 and $10 are left alone.
+pub fn replace_math(in_buf: &str) -> String {
+    let mut out = String::with_capacity(in_buf.len());
+    let mut in_fence = false;
+
+    let mut paragraphs = String::new();
+    for line in in_buf.lines() {
+        let trimmed = line.trim_left();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            if !in_fence {
+                out.push_str(&replace_spans(&paragraphs));
+                paragraphs.clear();
+            }
+            in_fence = !in_fence;
+            out.push_str(line);
+            out.push('\n');
+        }
+        else if in_fence {
+            out.push_str(line);
+            out.push('\n');
+        }
+        else {
+            paragraphs.push_str(line);
+            paragraphs.push('\n');
+        }
+    }
+    out.push_str(&replace_spans(&paragraphs));
+    out
+}
+
+// Works on byte offsets into `text`, so looking ahead for a closing delimiter never copies the rest
+fn replace_spans(text: &str) -> String {
+    let mut out  = String::with_capacity(text.len());
+    let mut pos  = 0;
+    let mut prev = ' ';
+    // Inline math ends with its paragraph, and once a $$ has no closing $$ none after it has
+    let mut para_end     = 0;
+    let mut display_open = true;
+    // The first $ able to close inline math after the last search, and where that search stopped
+    let mut closer : (Option<usize>, usize) = (None, 0);
+
+    while let Some(c) = text[pos..].chars().next() {
+        pos += c.len_utf8();
+        let rest = &text[pos..];
+
+        match c {
+            '\\' => {
+                out.push(c);
+                if let Some(n) = rest.chars().next() {
+                    out.push(n);
+                    pos += n.len_utf8();
+                    prev = n;
+                }
+                continue;
+            },
+            '`' => {
+                // Copy inline code verbatim, up to a closing run of the same length. An unclosed
+                // run is just backticks.
+                let run = 1 + backtick_run(rest);
+                out.push_str(&text[pos - 1..pos - 1 + run]);
+                pos += run - 1;
+
+                let code = &text[pos..];
+                let mut from = 0;
+                while let Some(i) = code[from..].find('`') {
+                    let start = from + i;
+                    let close = backtick_run(&code[start..]);
+                    if close == run {
+                        out.push_str(&code[..start + close]);
+                        pos += start + close;
+                        break;
+                    }
+                    from = start + close;
+                }
+                prev = '`';
+                continue;
+            },
+            '$' if rest.starts_with('$') => {
+                pos += 1;
+                let end = if display_open { rest[1..].find("$$") } else { None };
+                if let Some(end) = end {
+                    let tex = &rest[1..end + 1];
+                    out.push_str(&format!("\n\n<div class=\"math\">{}</div>\n\n", to_mathml(tex, true)));
+                    pos += end + 2;
+                    prev = ' ';
+                    continue;
+                }
+                display_open = false;
+                out.push_str("$$");
+            },
+            '$' if !prev.is_alphanumeric() => {
+                if pos > para_end {
+                    para_end = pos + rest.find("\n\n").unwrap_or(rest.len());
+                }
+                // Whether a $ can close math doesn't depend on where it opened, so the last search
+                // still holds if it stopped beyond here
+                let searched = match closer {
+                    (Some(close), _) => close > pos,
+                    (None, stop)     => pos < stop,
+                };
+                if !searched {
+                    closer = (None, para_end);
+                    for (i, n) in text[pos..para_end].char_indices() {
+                        if n == '`' {
+                            closer = (None, pos + i);
+                            break;
+                        }
+                        if n == '$' && i > 0
+                                    && !text[..pos + i].ends_with(char::is_whitespace)
+                                    && !text[..pos + i].ends_with('\\')
+                                    && !text[pos + i + 1..].starts_with(|d: char| d.is_ascii_digit()) {
+                            closer = (Some(pos + i), pos + i);
+                            break;
+                        }
+                    }
+                }
+
+                let opens = rest.chars().next().map(|n| !n.is_whitespace()).unwrap_or(false);
+                match closer.0 {
+                    Some(end) if opens => {
+                        out.push_str(&to_mathml(&text[pos..end], false));
+                        pos = end + 1;
+                        prev = '$';
+                        continue;
+                    },
+                    _ => out.push(c),
+                }
+            },
+            _ => out.push(c),
+        }
+        prev = c;
+    }
+    out
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{QuiltError, ConfigMenuItem, Page, escape_html, page_url};
+
+#[derive(Debug)]
+pub struct MenuEntry {
+    pub name   : String,
+    pub url    : String,
+    pub page   : Option<PathBuf>,
+    pub weight : i64,
+}
+
+// Maps a Quilt.toml page reference (eg. "blog/post" or "blog/post.md") to its key in Site::pages
+fn page_key(reference: &str) -> PathBuf {
+    let mut path = PathBuf::from(reference.trim_matches('/'));
+    if path.extension().is_some() {
+        path.set_extension("");
+    }
+    if path.starts_with("site") {
+        path
+    }
+    else {
+        PathBuf::from("site").join(path)
+    }
+}
+
+fn page_name(page: &Page) -> String {
+    match page.page_toml.title {
+        Some(ref title) => title.to_owned(),
+        None            => page.name.to_owned(),
+    }
+}
+
+pub fn resolve(config: &HashMap<String, Vec<ConfigMenuItem>>,
+               pages : &HashMap<PathBuf, Page>) -> Result<HashMap<String, Vec<MenuEntry>>, QuiltError> {
+    let mut menus : HashMap<String, Vec<MenuEntry>> = HashMap::new();
+
+    for (menu, items) in config {
+        let mut entries = vec![];
+        for item in items {
+            let entry = match (&item.page, &item.url) {
+                (&Some(ref reference), _) => {
+                    let key = page_key(reference);
+                    let page = match pages.get(&key) {
+                        Some(page) if page.has_content() => page,
+                        _ => return Err(QuiltError {source : "Menu".to_owned(),
+                                                    message: format!("Menu '{}' references nonexistent page {}",
+                                                                     menu, reference)}),
+                    };
+                    MenuEntry {name  : item.name.clone().unwrap_or_else(|| page_name(page)),
+                               url   : page_url(&key),
+                               page  : Some(key),
+                               weight: item.weight,                                       }
+                },
+                (&None, &Some(ref url)) => {
+                    let name = match item.name {
+                        Some(ref name) => name.to_owned(),
+                        None => return Err(QuiltError {source : "Menu".to_owned(),
+                                                       message: format!("Menu '{}' has an entry for {} with no name",
+                                                                        menu, url)}),
+                    };
+                    MenuEntry {name: name, url: url.to_owned(), page: None, weight: item.weight}
+                },
+                (&None, &None) => {
+                    return Err(QuiltError {source : "Menu".to_owned(),
+                                           message: format!("Menu '{}' has an entry with neither a page nor a url",
+                                                            menu)})
+                },
+            };
+            entries.push(entry);
+        }
+        menus.insert(menu.to_owned(), entries);
+    }
+
+    for (path, page) in pages {
+        if !page.has_content() {
+            continue;
+        }
+        if let Some(ref menu) = page.page_toml.menu {
+            let entries = menus.entry(menu.to_owned()).or_insert_with(Vec::new);
+            if entries.iter().any(|e| e.page.as_ref() == Some(path)) {
+                continue;
+            }
+            entries.push(MenuEntry {name  : page_name(page),
+                                    url   : page_url(path),
+                                    page  : Some(path.clone()),
+                                    weight: page.page_toml.weight.unwrap_or(0),});
+        }
+    }
+
+    for entries in menus.values_mut() {
+        entries.sort_by(|a, b| (a.weight, &a.name).cmp(&(b.weight, &b.name)));
+    }
+
+    Ok(menus)
+}
+
+pub fn render(entries: &[MenuEntry], current: &Path) -> String {
+    let current_url = page_url(current);
+    let mut out = String::from("<ul class=\"menu\">\n");
+
+    for entry in entries {
+        let section_url = entry.url.trim_right_matches(".html").to_owned() + "/";
+        let class = {
+            if entry.page.as_ref().map(|p| p == current).unwrap_or(false) || entry.url == current_url {
+                " class=\"active\""
+            }
+            else if current_url.starts_with(&section_url) {
+                " class=\"ancestor\""
+            }
+            else {
+                ""
+            }
+        };
+
+        out.push_str(&format!("<li{}><a href=\"{}\">{}</a></li>\n",
+                              class, escape_html(&entry.url), escape_html(&entry.name)));
+    }
+
+    out.push_str("</ul>");
+    out
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{Page, escape_html, page_url};
+use summary::Summary;
+use stats::PageStats;
+
+#[derive(Debug, Serialize)]
+pub struct NavPage {
+    pub name    : String,
+    pub title   : String,
+    pub url     : String,
+    pub weight  : i64,
+    pub summary : String,
+    pub stats   : PageStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NavSection {
+    pub name     : String,
+    pub title    : String,
+    pub url      : Option<String>,
+    pub weight   : i64,
+    pub pages    : Vec<NavPage>,
+    pub sections : Vec<NavSection>,
+}
+
+fn nav_page(path: &Path, page: &Page, summary: &Summary) -> NavPage {
+    NavPage {
+        name    : page.name.clone(),
+        title   : page.page_toml.title.clone().unwrap_or_else(|| page.name.clone()),
+        url     : page_url(path),
+        weight  : page.page_toml.weight.unwrap_or(0),
+        summary : summary.html.clone(),
+        stats   : PageStats::default(),
+    }
+}
+
+fn build_section(id: usize, sections: &[PathBuf], by_section: &mut HashMap<usize, Vec<NavPage>>) -> NavSection {
+    let sec_path = &sections[id];
+    let is_root  = id == 0;
+
+    let mut pages = by_section.remove(&id).unwrap_or_else(Vec::new);
+    pages.sort_by(|a, b| (a.weight, &a.title).cmp(&(b.weight, &b.title)));
+
+    // The index page of a subsection stands for the section itself
+    let index = if is_root { None } else {
+        pages.iter().position(|p| p.name == "index").map(|i| pages.remove(i))
+    };
+
+    let mut children = vec![];
+    for (child_id, child_path) in sections.iter().enumerate() {
+        if child_id != id && child_path.parent() == Some(sec_path.as_path()) {
+            children.push(build_section(child_id, sections, by_section));
+        }
+    }
+    children.sort_by(|a, b| (a.weight, &a.title).cmp(&(b.weight, &b.title)));
+
+    let name = sec_path.file_name().unwrap().to_string_lossy().into_owned();
+
+    NavSection {
+        title    : index.as_ref().map(|p| p.title.clone()).unwrap_or_else(|| name.clone()),
+        url      : index.as_ref().map(|p| p.url.clone()),
+        weight   : index.as_ref().map(|p| p.weight).unwrap_or(0),
+        name     : name,
+        pages    : pages,
+        sections : children,
+    }
+}
+
+pub fn build_tree(sections : &[PathBuf], pages: &HashMap<PathBuf, Page>,
+                  summaries: &HashMap<PathBuf, Summary>) -> NavSection {
+    let mut by_section : HashMap<usize, Vec<NavPage>> = HashMap::new();
+    for (path, page) in pages {
+        if page.has_content() {
+            by_section.entry(page.section_id).or_insert_with(Vec::new).push(nav_page(path, page, &summaries[path]));
+        }
+    }
+
+    build_section(0, sections, &mut by_section)
+}
+
+// Fills in word counts and reading times once the pages have been rendered
+pub fn set_stats(section: &mut NavSection, stats: &HashMap<String, PageStats>) {
+    for page in section.pages.iter_mut() {
+        if let Some(s) = stats.get(&page.url) {
+            page.stats = *s;
+        }
+    }
+    for sub in section.sections.iter_mut() {
+        set_stats(sub, stats);
+    }
+}
+
+fn render_section(section: &NavSection, current_url: &str, out: &mut String) {
+    out.push_str("<ul>\n");
+    for page in &section.pages {
+        let class = if page.url == current_url { " class=\"active\"" } else { "" };
+        out.push_str(&format!("<li{}><a href=\"{}\">{}</a></li>\n",
+                              class, escape_html(&page.url), escape_html(&page.title)));
+    }
+    for sub in &section.sections {
+        let class = if sub.url.as_ref().map(|u| u == current_url).unwrap_or(false) {
+            "section active"
+        } else {
+            "section"
+        };
+        out.push_str(&format!("<li class=\"{}\">", class));
+        match sub.url {
+            Some(ref url) => out.push_str(&format!("<a href=\"{}\">{}</a>\n",
+                                                   escape_html(url), escape_html(&sub.title))),
+            None          => out.push_str(&format!("<span>{}</span>\n", escape_html(&sub.title))),
+        }
+        render_section(sub, current_url, out);
+        out.push_str("</li>\n");
+    }
+    out.push_str("</ul>\n");
+}
+
+pub fn render(tree: &NavSection, current: &Path) -> String {
+    let mut out = String::from("<nav class=\"nav-tree\">\n");
+    render_section(tree, &page_url(current), &mut out);
+    out.push_str("</nav>");
+    out
+}
@@ -0,0 +1,253 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde_json::{self, Value};
+
+use super::{QuiltError, Page, RenderOptions, Rendered, escape_html, page_url};
+use formats::{ContentFormat, Markdown, block_placeholder};
+
+fn notebook_err(path: &Path, message: String) -> QuiltError {
+    QuiltError {source : "Notebook".to_owned(),
+                message: format!("{}: {}", path.display(), message)}
+}
+
+// Cell sources and output text are stored either as a string or as a list of lines
+fn text(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        Value::Array(ref lines) => lines.iter().filter_map(|l| l.as_str()).collect::<Vec<&str>>().concat(),
+        _ => String::new(),
+    }
+}
+
+// Image outputs and attachments, most preferred first, with the extension they are written with
+const IMAGE_TYPES : &'static [(&'static str, &'static str)] = &[("image/png", "png"), ("image/jpeg", "jpg"),
+                                                                ("image/gif", "gif")];
+
+const BASE64 : &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Images are stored base64 encoded, wrapped over several lines
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let mut out  = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc  = 0u32;
+    let mut bits = 0;
+
+    for c in data.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            break;
+        }
+        let val = BASE64.iter().position(|&b| b == c)?;
+        acc   = (acc << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+// Tracebacks are coloured with ANSI escape sequences
+fn strip_ansi(text: &str) -> String {
+    let mut out   = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for n in chars.by_ref() {
+                if n.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+        else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+struct Converter<'n> {
+    path     : &'n Path,
+    // Output URL and build-relative path of extracted files, without the extension
+    url_base : String,
+    out_base : PathBuf,
+    files    : Vec<(PathBuf, Vec<u8>)>,
+    // Outputs, spliced in after the markdown is rendered
+    blocks   : Vec<String>,
+}
+
+impl<'n> Converter<'n> {
+    fn extract(&mut self, name: &str, ext: &str, data: Vec<u8>) -> String {
+        let file = format!("{}-{}.{}", self.out_base.file_name().unwrap().to_string_lossy(), name, ext);
+        self.files.push((self.out_base.with_file_name(&file), data));
+        format!("{}-{}.{}", self.url_base, name, ext)
+    }
+
+    fn image(&mut self, name: &str, ext: &str, data: &Value) -> Result<String, QuiltError> {
+        match decode_base64(&text(data)) {
+            Some(bytes) => Ok(self.extract(name, ext, bytes)),
+            None => Err(notebook_err(self.path, format!("output {} has invalid base64 image data", name))),
+        }
+    }
+
+    // The richest representation we can show of a display_data or execute_result output
+    fn data(&mut self, name: &str, data: &Value) -> Result<String, QuiltError> {
+        for &(mime, ext) in IMAGE_TYPES {
+            if let Some(image) = data.get(mime) {
+                let url = self.image(name, ext, image)?;
+                return Ok(format!("<img src=\"{}\" alt=\"\">", escape_html(&url)));
+            }
+        }
+        if let Some(svg) = data.get("image/svg+xml") {
+            let url = self.extract(name, "svg", text(svg).into_bytes());
+            return Ok(format!("<img src=\"{}\" alt=\"\">", escape_html(&url)));
+        }
+        if let Some(html) = data.get("text/html") {
+            return Ok(text(html));
+        }
+        if let Some(plain) = data.get("text/plain") {
+            return Ok(format!("<pre>{}</pre>", escape_html(&text(plain))));
+        }
+        Ok(String::new())
+    }
+
+    fn output(&mut self, name: &str, output: &Value) -> Result<String, QuiltError> {
+        let kind = output.get("output_type").and_then(|t| t.as_str()).unwrap_or("");
+        let body = match kind {
+            "stream" => {
+                let stream = output.get("name").and_then(|n| n.as_str()).unwrap_or("stdout");
+                let text   = output.get("text").map(text).unwrap_or_default();
+                format!("<pre class=\"output {}\">{}</pre>", escape_html(stream), escape_html(&text))
+            },
+            "execute_result" | "display_data" => {
+                let shown = match output.get("data") {
+                    Some(data) => self.data(name, data)?,
+                    None       => String::new(),
+                };
+                if shown.is_empty() {
+                    return Ok(shown);
+                }
+                format!("<div class=\"output\">\n{}\n</div>", shown)
+            },
+            "error" => {
+                let trace : Vec<String> = output.get("traceback").and_then(|t| t.as_array())
+                                                .map(|t| t.iter().map(text).collect()).unwrap_or_default();
+                format!("<pre class=\"output error\">{}</pre>", escape_html(&strip_ansi(&trace.join("\n"))))
+            },
+            _ => return Ok(String::new()),
+        };
+        self.blocks.push(body);
+        Ok(block_placeholder(self.blocks.len() - 1))
+    }
+
+    // Markdown cells as they are, code cells as fenced code and outputs as placeholders
+    fn convert(&mut self, notebook: &Value) -> Result<String, QuiltError> {
+        let cells = match notebook.get("cells").and_then(|c| c.as_array()) {
+            Some(cells) => cells,
+            None => return Err(notebook_err(self.path, "no cells (only nbformat 4 notebooks are supported)".to_owned())),
+        };
+        let lang = notebook.pointer("/metadata/kernelspec/language")
+                           .or_else(|| notebook.pointer("/metadata/language_info/name"))
+                           .and_then(|l| l.as_str()).unwrap_or("python").to_owned();
+
+        let mut out = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            let source = cell.get("source").map(text).unwrap_or_default();
+            match cell.get("cell_type").and_then(|t| t.as_str()).unwrap_or("") {
+                "markdown" => {
+                    // Images pasted into a cell are stored with it as attachments
+                    let mut source = source;
+                    if let Some(attachments) = cell.get("attachments").and_then(|a| a.as_object()) {
+                        for (n, (name, data)) in attachments.iter().enumerate() {
+                            let image = IMAGE_TYPES.iter()
+                                                   .filter_map(|&(mime, ext)| data.get(mime).map(|d| (ext, d))).next();
+                            if let Some((ext, image)) = image {
+                                let url = self.image(&format!("{}-a{}", i, n), ext, image)?;
+                                source = source.replace(&format!("attachment:{}", name), &url);
+                            }
+                        }
+                    }
+                    out.push_str(&source);
+                },
+                "code" => {
+                    if !source.trim().is_empty() {
+                        let longest = source.split(|c| c != '`').map(|run| run.len()).max().unwrap_or(0);
+                        let fence   = "`".repeat((longest + 1).max(3));
+                        out.push_str(&format!("{}{}\n{}\n{}", fence, lang, source.trim_right(), fence));
+                    }
+                    let outputs = cell.get("outputs").and_then(|o| o.as_array()).cloned().unwrap_or_default();
+                    for (j, output) in outputs.iter().enumerate() {
+                        let html = self.output(&format!("{}-{}", i, j), output)?;
+                        if !html.is_empty() {
+                            out.push_str("\n\n");
+                            out.push_str(&html);
+                        }
+                    }
+                },
+                _ => (),
+            }
+            out.push_str("\n\n");
+        }
+        Ok(out)
+    }
+}
+
+// Jupyter notebooks, rendered through the markdown pipeline with outputs included and images
+// extracted next to the page
+pub struct Notebook;
+
+impl ContentFormat for Notebook {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ipynb"]
+    }
+
+    // The markdown cells, so notebooks get summaries and wiki links like any other page
+    fn markdown_source<'a>(&self, in_buf: &'a str) -> Option<Cow<'a, str>> {
+        let notebook : Value = serde_json::from_str(in_buf).ok()?;
+        let cells = notebook.get("cells")?.as_array()?.iter()
+                            .filter(|cell| cell.get("cell_type").and_then(|t| t.as_str()) == Some("markdown"))
+                            .map(|cell| cell.get("source").map(text).unwrap_or_default())
+                            .collect::<Vec<String>>();
+        Some(Cow::Owned(cells.join("\n\n")))
+    }
+
+    fn render(&self, _page: &Page, in_buf: &str, path: &Path, temp: Option<PathBuf>,
+              vars: &mut HashMap<String, String>, opts: &RenderOptions) -> Result<Rendered, QuiltError> {
+        let notebook : Value = match serde_json::from_str(in_buf) {
+            Ok(notebook) => notebook,
+            Err(err)     => return Err(notebook_err(path, format!("could not decode: {}", err))),
+        };
+
+        let url = page_url(opts.page_path);
+        let mut converter = Converter {
+            path     : path,
+            url_base : url.trim_right_matches(".html").to_owned(),
+            out_base : opts.page_path.strip_prefix("site").unwrap_or(opts.page_path).to_path_buf(),
+            files    : vec![],
+            blocks   : vec![],
+        };
+        let md_buf = converter.convert(&notebook)?;
+
+        let mut rendered = Markdown.render_with_blocks(&md_buf, &converter.blocks, path, temp, vars, opts)?;
+        rendered.files.extend(converter.files);
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_source_is_the_markdown_cells() {
+        let notebook = r##"{"nbformat": 4, "metadata": {}, "cells": [
+            {"cell_type": "markdown", "source": ["# Analysis\n", "\n", "See [[Intro]]."]},
+            {"cell_type": "code", "source": "print('not markdown')", "outputs": []},
+            {"cell_type": "markdown", "source": "More."}
+        ]}"##;
+        let source = Notebook.markdown_source(notebook).map(|s| s.into_owned());
+        assert_eq!(source, Some("# Analysis\n\nSee [[Intro]].\n\nMore.".to_owned()));
+        assert_eq!(Notebook.markdown_source("not json"), None);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use super::{ConfigRelated, Page, escape_html, page_url};
+
+#[derive(Debug)]
+pub struct Related {
+    pub title : String,
+    pub url   : String,
+    pub score : i64,
+}
+
+fn shared(a: &[String], b: &[String]) -> i64 {
+    let a_set : HashSet<&String> = a.iter().collect();
+    let b_set : HashSet<&String> = b.iter().collect();
+    a_set.intersection(&b_set).count() as i64
+}
+
+// Ranks every other page by the tags and categories it shares with each page
+pub fn compute(config: &ConfigRelated, pages: &HashMap<PathBuf, Page>) -> HashMap<PathBuf, Vec<Related>> {
+    let mut related = HashMap::new();
+
+    for (path, page) in pages {
+        if !page.has_content() {
+            continue;
+        }
+        let ptoml = &page.page_toml;
+
+        let mut scored : Vec<Related> = vec![];
+        for (other_path, other) in pages {
+            if other_path == path || !other.has_content() {
+                continue;
+            }
+            let otoml = &other.page_toml;
+
+            let score = config.tags_weight * shared(&ptoml.tags, &otoml.tags)
+                      + config.categories_weight * shared(&ptoml.categories, &otoml.categories);
+
+            if score > 0 {
+                scored.push(Related {
+                    title : otoml.title.clone().unwrap_or_else(|| other.name.clone()),
+                    url   : page_url(other_path),
+                    score : score,
+                });
+            }
+        }
+
+        scored.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.title.cmp(&b.title)));
+        scored.truncate(config.limit);
+        related.insert(path.clone(), scored);
+    }
+
+    related
+}
+
+pub fn render(related: &[Related]) -> String {
+    if related.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::from("<ul class=\"related\">\n");
+    for r in related {
+        out.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", escape_html(&r.url), escape_html(&r.title)));
+    }
+    out.push_str("</ul>");
+    out
+}
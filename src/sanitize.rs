@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+
+use super::ConfigSanitize;
+use external::parse_attrs;
+
+// Everything Quilt itself produces from markdown, including math, sidenotes and task lists
+pub const DEFAULT_TAGS : &'static [&'static str] = &[
+    "a", "abbr", "aside", "b", "blockquote", "br", "caption", "code", "dd", "del", "div", "dl", "dt", "em",
+    "figcaption", "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img", "input", "ins", "kbd", "label",
+    "li", "mark", "nav", "ol", "p", "pre", "q", "s", "section", "small", "span", "strong", "sub", "sup", "table",
+    "tbody", "td", "tfoot", "th", "thead", "tr", "u", "ul",
+    "math", "semantics", "annotation", "mrow", "mi", "mn", "mo", "mtext", "mspace", "msub", "msup", "msubsup",
+    "munder", "mover", "munderover", "mfrac", "msqrt", "mroot", "mstyle", "merror",
+];
+
+pub const DEFAULT_ATTRIBUTES : &'static [&'static str] = &[
+    "href", "src", "alt", "title", "id", "class", "colspan", "rowspan", "align", "start", "type", "checked",
+    "disabled", "for", "lang", "width", "height", "target", "rel",
+    "display", "encoding", "stretchy", "accent", "mathvariant", "linethickness", "linebreak",
+];
+
+pub const DEFAULT_SCHEMES : &'static [&'static str] = &["http", "https", "mailto"];
+
+// Elements whose content goes along with them
+const DROP_CONTENT : &'static [&'static str] = &["script", "style", "template", "noscript", "textarea"];
+
+const URL_ATTRIBUTES : &'static [&'static str] = &["href", "src", "cite", "action", "formaction", "poster",
+                                                    "background", "xlink:href"];
+
+struct Policy<'c> {
+    tags       : HashSet<&'c str>,
+    attributes : HashSet<&'c str>,
+    schemes    : HashSet<&'c str>,
+}
+
+// Undoes the character references and whitespace that could disguise a scheme like `java&#9;script:`.
+// Browsers end a numeric reference at the first character that is not a digit, with or without a `;`.
+// Returns None for relative URLs, and an empty scheme, which no policy allows, for any reference left over.
+fn url_scheme(url: &str) -> Option<String> {
+    let mut plain = String::new();
+    let mut rest  = url;
+    while let Some(c) = rest.chars().next() {
+        if c == '&' {
+            let decoded = {
+                if rest.starts_with("&#x") || rest.starts_with("&#X") {
+                    let digits = rest[3..].find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len() - 3);
+                    u32::from_str_radix(&rest[3..3 + digits], 16).ok().and_then(::std::char::from_u32)
+                                                                   .map(|d| (d, 3 + digits))
+                }
+                else if rest.starts_with("&#") {
+                    let digits = rest[2..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - 2);
+                    rest[2..2 + digits].parse::<u32>().ok().and_then(::std::char::from_u32)
+                                       .map(|d| (d, 2 + digits))
+                }
+                else {
+                    let end = rest.find(';').unwrap_or(0);
+                    let named = match &rest[1..end.max(1)] {
+                        "colon" => Some(':'), "tab" => Some('\t'), "newline" => Some('\n'),
+                        "amp" => Some('&'), _ => None,
+                    };
+                    named.map(|d| (d, end))
+                }
+            };
+            if let Some((d, len)) = decoded {
+                plain.push(d);
+                rest = &rest[len..];
+                if rest.starts_with(';') {
+                    rest = &rest[1..];
+                }
+                continue;
+            }
+        }
+        plain.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    let plain : String = plain.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect();
+    if plain.contains("&#") {
+        return Some(String::new());
+    }
+    let colon = match plain.find(':') {
+        Some(colon) => colon,
+        None        => return None,
+    };
+    match plain.find(|c| c == '/' || c == '?' || c == '#') {
+        Some(other) if other < colon => None,
+        _ => Some(plain[..colon].to_lowercase()),
+    }
+}
+
+fn write_tag(name: &str, attrs: &[(String, Option<String>)], policy: &Policy, self_closing: bool) -> String {
+    let mut tag = format!("<{}", name);
+    for &(ref attr, ref value) in attrs {
+        if attr.starts_with("on") || !policy.attributes.contains(attr.as_str()) {
+            continue;
+        }
+        match *value {
+            Some(ref value) => {
+                if URL_ATTRIBUTES.contains(&attr.as_str()) {
+                    if let Some(scheme) = url_scheme(value) {
+                        if !policy.schemes.contains(scheme.as_str()) {
+                            continue;
+                        }
+                    }
+                }
+                tag.push_str(&format!(" {}=\"{}\"", attr, value.replace('"', "&quot;").replace('<', "&lt;")));
+            },
+            None => tag.push_str(&format!(" {}", attr)),
+        }
+    }
+    tag.push_str(if self_closing { "/>" } else { ">" });
+    tag
+}
+
+// Removes elements and attributes outside the policy from rendered HTML, along with comments,
+// event handlers and URLs whose scheme is not allowed. Text is kept.
+pub fn sanitize(html: &str, config: &ConfigSanitize) -> String {
+    let policy = Policy {
+        tags       : config.allowed_tags.iter().map(|t| t.as_str()).collect(),
+        attributes : config.allowed_attributes.iter().map(|a| a.as_str()).collect(),
+        schemes    : config.allowed_schemes.iter().map(|s| s.as_str()).collect(),
+    };
+
+    let mut out  = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if after.starts_with("!--") {
+            rest = match after.find("-->") {
+                Some(end) => &after[end + 3..],
+                None      => "",
+            };
+            continue;
+        }
+
+        let closing = after.starts_with('/');
+        let body    = if closing { &after[1..] } else { after };
+        if !body.starts_with(|c: char| c.is_ascii_alphabetic()) && !after.starts_with('!') && !after.starts_with('?') {
+            out.push_str("&lt;");
+            rest = after;
+            continue;
+        }
+
+        // The tag ends at the first > outside a quoted value
+        let mut quote = None;
+        let end = after.char_indices().find(|&(_, c)| {
+            match quote {
+                Some(q) if c == q => { quote = None; false },
+                Some(_)           => false,
+                None if c == '"' || c == '\'' => { quote = Some(c); false },
+                None              => c == '>',
+            }
+        }).map(|(i, _)| i);
+        let end = match end {
+            Some(end) => end,
+            None => {
+                out.push_str("&lt;");
+                rest = after;
+                continue;
+            },
+        };
+
+        let inner    = if closing { &after[1..end] } else { &after[..end] };
+        let name_end = inner.find(|c: char| c.is_whitespace() || c == '/').unwrap_or(inner.len());
+        let name     = inner[..name_end].to_lowercase();
+        rest = &after[end + 1..];
+
+        if !closing && DROP_CONTENT.contains(&name.as_str()) {
+            // ASCII lowercasing keeps byte offsets the same, so they index `rest` too
+            let lower = rest.to_ascii_lowercase();
+            rest = match lower.find(&format!("</{}", name)) {
+                Some(close) => {
+                    let tail = &rest[close..];
+                    match tail.find('>') {
+                        Some(gt) => &tail[gt + 1..],
+                        None     => "",
+                    }
+                },
+                None => "",
+            };
+            continue;
+        }
+        if !policy.tags.contains(name.as_str()) {
+            continue;
+        }
+
+        if closing {
+            out.push_str(&format!("</{}>", name));
+        }
+        else {
+            let attrs = parse_attrs(&inner[name_end..]);
+            out.push_str(&write_tag(&name, &attrs, &policy, inner.trim_right().ends_with('/')));
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConfigSanitize;
+
+    #[test]
+    fn drops_script_with_non_ascii_content() {
+        let config = ConfigSanitize::default();
+        // The Kelvin sign shrinks when lowercased and İ grows
+        assert_eq!(sanitize("<p>a</p><script>\u{212A}</script><p>b</p>", &config), "<p>a</p><p>b</p>");
+        assert_eq!(sanitize("<p>a</p><script>İİİİ</script><p>b</p>", &config), "<p>a</p><p>b</p>");
+        assert_eq!(sanitize("<style>\u{212A}İ</STYLE>İ<b>c</b>", &config), "İ<b>c</b>");
+    }
+
+    #[test]
+    fn drops_unclosed_script() {
+        assert_eq!(sanitize("<p>a</p><script>\u{212A} alert(1)", &ConfigSanitize::default()), "<p>a</p>");
+    }
+
+    #[test]
+    fn drops_disguised_schemes() {
+        let config = ConfigSanitize::default();
+        for href in &["javascript:alert(1)", "JavaScript:alert(1)", "java&#9;script:alert(1)",
+                      "&#106;avascript:alert(1)", "&#106avascript:alert(1)", "&#x6A;avascript:alert(1)",
+                      "&#x6aavascript:alert(1)", "javascript&#58alert(1)", "javascript&#x3A;alert(1)",
+                      "javascript&colon;alert(1)", "&#0000106avascript:alert(1)", "&#99999999999avascript:x",
+                      "&amp;#106;avascript:alert(1)"] {
+            assert_eq!(sanitize(&format!("<a href=\"{}\">x</a>", href), &config), "<a>x</a>", "{}", href);
+        }
+    }
+
+    #[test]
+    fn keeps_allowed_and_relative_urls() {
+        let config = ConfigSanitize::default();
+        for href in &["https://example.com/", "mailto:a@example.com", "docs/page.html", "#section",
+                      "page.html?a=b:c", "/a&amp;b", "&#104;ttps://example.com/"] {
+            let html = format!("<a href=\"{}\">x</a>", href);
+            assert_eq!(sanitize(&html, &config), html);
+        }
+    }
+}
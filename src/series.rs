@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{QuiltError, Page, escape_html, page_url};
+
+#[derive(Debug)]
+pub struct SeriesEntry {
+    pub path  : PathBuf,
+    pub title : String,
+    pub url   : String,
+    pub part  : Option<i64>,
+}
+
+// Groups pages by their `series`, ordered by `series_part` (unnumbered parts follow, by title)
+pub fn collect(pages: &HashMap<PathBuf, Page>) -> Result<HashMap<String, Vec<SeriesEntry>>, QuiltError> {
+    let mut series : HashMap<String, Vec<SeriesEntry>> = HashMap::new();
+
+    for (path, page) in pages {
+        if !page.has_content() {
+            continue;
+        }
+        if let Some(ref name) = page.page_toml.series {
+            let entry = SeriesEntry {
+                path  : path.clone(),
+                title : page.page_toml.title.clone().unwrap_or_else(|| page.name.clone()),
+                url   : page_url(path),
+                part  : page.page_toml.series_part,
+            };
+            series.entry(name.to_owned()).or_insert_with(Vec::new).push(entry);
+        }
+    }
+
+    for (name, entries) in series.iter_mut() {
+        entries.sort_by(|a, b| (a.part.is_none(), a.part, &a.title).cmp(&(b.part.is_none(), b.part, &b.title)));
+
+        for pair in entries.windows(2) {
+            if pair[0].part.is_some() && pair[0].part == pair[1].part {
+                return Err(QuiltError {source : "Series".to_owned(),
+                                       message: format!("{} and {} are both part {} of series '{}'",
+                                                        pair[0].path.display(), pair[1].path.display(),
+                                                        pair[0].part.unwrap(), name)});
+            }
+        }
+    }
+
+    Ok(series)
+}
+
+fn link(entry: &SeriesEntry) -> String {
+    format!("<a href=\"{}\">{}</a>", escape_html(&entry.url), escape_html(&entry.title))
+}
+
+// Template variables for the page at `current`; empty when it is not part of a series
+pub fn vars(series: &HashMap<String, Vec<SeriesEntry>>, page: &Page, current: &Path,
+            vars: &mut HashMap<String, String>) {
+    let mut name  = String::new();
+    let mut part  = String::new();
+    let mut total = String::new();
+    let mut list  = String::new();
+    let mut prev  = String::new();
+    let mut next  = String::new();
+
+    if let Some(ref series_name) = page.page_toml.series {
+        if let Some(entries) = series.get(series_name) {
+            let pos = entries.iter().position(|e| e.path == current).unwrap();
+
+            name  = escape_html(series_name);
+            part  = format!("{}", pos + 1);
+            total = format!("{}", entries.len());
+
+            list.push_str("<ol class=\"series\">\n");
+            for (i, entry) in entries.iter().enumerate() {
+                if i == pos {
+                    list.push_str(&format!("<li class=\"active\">{}</li>\n", escape_html(&entry.title)));
+                }
+                else {
+                    list.push_str(&format!("<li>{}</li>\n", link(entry)));
+                }
+            }
+            list.push_str("</ol>");
+
+            if pos > 0 {
+                prev = link(&entries[pos - 1]);
+            }
+            if let Some(entry) = entries.get(pos + 1) {
+                next = link(entry);
+            }
+        }
+    }
+
+    vars.insert("series.name".to_owned() , name );
+    vars.insert("series.part".to_owned() , part );
+    vars.insert("series.total".to_owned(), total);
+    vars.insert("series.list".to_owned() , list );
+    vars.insert("series.prev".to_owned() , prev );
+    vars.insert("series.next".to_owned() , next );
+}
@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::{QuiltError, escape_html};
+use links::normalise;
+use math::backtick_run;
+
+const OPEN  : &'static str = "{{<";
+const CLOSE : &'static str = ">}}";
+
+// Includes nested deeper than this are taken to be a cycle the stack check cannot see, e.g. through a symlink
+const MAX_INCLUDE_DEPTH : usize = 32;
+
+#[derive(Debug)]
+pub struct Shortcode {
+    pub name : String,
+    pub args : HashMap<String, String>,
+    pub line : usize,
+}
+
+fn shortcode_err(source: &Path, line: usize, message: String) -> QuiltError {
+    QuiltError {source : "Shortcode".to_owned(),
+                message: format!("{}:{}: {}", source.display(), line, message)}
+}
+
+// Splits `name key="a value" other=x "positional"` into a name and its arguments.
+// Positional arguments are keyed by their index: 0, 1, ...
+fn parse_call(call: &str, line: usize, source: &Path) -> Result<Shortcode, QuiltError> {
+    // Each token is an optional key and a value, with quotes and escapes resolved
+    let mut tokens : Vec<(Option<String>, String)> = vec![];
+    let mut chars  = call.chars().peekable();
+
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key   : Option<String> = None;
+        let mut value = String::new();
+        let mut quoted = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted => {
+                    quoted = false;
+                    break;
+                },
+                '"' => quoted = true,
+                '\\' if quoted => {
+                    if let Some(escaped) = chars.next() {
+                        value.push(escaped);
+                    }
+                },
+                '=' if !quoted && key.is_none() => {
+                    key = Some(value.clone());
+                    value.clear();
+                },
+                c if c.is_whitespace() && !quoted => break,
+                _ => value.push(c),
+            }
+        }
+
+        if quoted {
+            return Err(shortcode_err(source, line, format!("unterminated string in shortcode '{}'", call.trim())));
+        }
+        tokens.push((key, value));
+    }
+
+    let mut tokens = tokens.into_iter();
+    let name = match tokens.next() {
+        Some((None, name)) => name,
+        _ => return Err(shortcode_err(source, line, format!("malformed shortcode '{}'", call.trim()))),
+    };
+
+    let mut args = HashMap::new();
+    let mut positional = 0;
+    for (key, val) in tokens {
+        let key = key.unwrap_or_else(|| {
+            positional += 1;
+            format!("{}", positional - 1)
+        });
+        args.insert(key, val);
+    }
+
+    Ok(Shortcode {name: name, args: args, line: line})
+}
+
+// Fills {{arg}} (required) and {{arg?}} (optional) placeholders from the shortcode's arguments
+fn render(template: &str, call: &Shortcode, source: &Path) -> Result<String, QuiltError> {
+    let mut out  = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None      => break,
+        };
+
+        let key      = rest[start + 2..end].trim();
+        let optional = key.ends_with('?');
+        let key      = key.trim_right_matches('?');
+
+        match call.args.get(key) {
+            Some(val) => out.push_str(&escape_html(val)),
+            None if optional => (),
+            None => return Err(shortcode_err(source, call.line,
+                                             format!("shortcode '{}' is missing required argument '{}'",
+                                                     call.name, key))),
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+// The character and length of the fence a line opens a fenced code block with
+fn opening_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_left();
+    let c = trimmed.chars().next()?;
+    let run = trimmed.len() - trimmed.trim_left_matches(c).len();
+    if (c == '`' || c == '~') && run >= 3 { Some((c, run)) } else { None }
+}
+
+// A block only closes on a fence of the same character, at least as long and with nothing after it
+fn closes_fence(line: &str, (c, len): (char, usize)) -> bool {
+    let trimmed = line.trim_left();
+    let rest    = trimmed.trim_left_matches(c);
+    trimmed.len() - rest.len() >= len && rest.trim().is_empty()
+}
+
+fn is_list_item(trimmed: &str) -> bool {
+    let marker = trimmed.trim_left_matches(|c: char| c.is_ascii_digit());
+    if marker.len() < trimmed.len() {
+        marker.starts_with(". ") || marker.starts_with(") ")
+    }
+    else {
+        ["- ", "* ", "+ "].iter().any(|m| trimmed.starts_with(m))
+    }
+}
+
+// The length of `text` up to the blank line ending its paragraph
+fn paragraph_len(text: &str) -> usize {
+    let mut len = 0;
+    for line in text.split('\n') {
+        if len > 0 && line.trim().is_empty() {
+            return len;
+        }
+        len += line.len() + 1;
+    }
+    text.len()
+}
+
+// The end of the first run of exactly `run` backticks in `text`
+fn closing_run(text: &str, run: usize) -> Option<usize> {
+    let mut from = 0;
+    while let Some(i) = text[from..].find('`') {
+        let start = from + i;
+        let close = backtick_run(&text[start..]);
+        if close == run {
+            return Some(start + close);
+        }
+        from = start + close;
+    }
+    None
+}
+
+// Finds each shortcode outside code, handing it to `resolve` and splicing in the result. Fenced
+// and indented code blocks and inline code spans are copied as they are, as is anything after a
+// backslash, so `\{{< name >}}` shows the shortcode itself.
+pub fn replace<F>(in_buf: &str, source: &Path, mut resolve: F) -> Result<String, QuiltError>
+    where F: FnMut(&Shortcode) -> Result<String, QuiltError> {
+    let mut out      = String::with_capacity(in_buf.len());
+    let mut fence    : Option<(char, usize)> = None;
+    let mut indented = false;
+    let mut in_list  = false;
+    let mut blank    = true;
+    let mut pending  : Option<(String, usize)> = None;
+    // The backtick run that opened a code span continuing onto the next line
+    let mut span     : Option<usize> = None;
+    // Code spans close within their paragraph. Runs already known not to, by length and paragraph end.
+    let mut para_end = 0;
+    let mut unclosed : Vec<(usize, usize)> = vec![];
+    let mut offset   = 0;
+
+    for (n, line) in in_buf.split('\n').enumerate() {
+        let line_start = offset;
+        offset += line.len() + 1;
+        if n > 0 {
+            match pending {
+                Some((ref mut call, _)) => call.push('\n'),
+                None                    => out.push('\n'),
+            }
+        }
+
+        if pending.is_none() && span.is_none() {
+            let was_blank = blank;
+            blank = line.trim().is_empty();
+            let code = match fence {
+                Some(open) => {
+                    if closes_fence(line, open) {
+                        fence = None;
+                    }
+                    true
+                },
+                None if opening_fence(line).is_some() => {
+                    fence = opening_fence(line);
+                    true
+                },
+                None => {
+                    // Indented code can't interrupt a paragraph, and indented lines in a list belong to it
+                    let deep = line.starts_with("    ") || line.starts_with('\t');
+                    indented = (indented && (deep || blank)) || (deep && was_blank && !in_list);
+                    if !blank && !deep {
+                        in_list = is_list_item(line.trim_left()) || (in_list && !was_blank);
+                    }
+                    indented
+                },
+            };
+            if code {
+                out.push_str(line);
+                continue;
+            }
+        }
+
+        let mut rest = line;
+        loop {
+            if let Some((mut call, start_line)) = pending.take() {
+                match rest.find(CLOSE) {
+                    Some(end) => {
+                        call.push_str(&rest[..end]);
+                        let shortcode = parse_call(&call, start_line, source)?;
+                        out.push_str(&resolve(&shortcode)?);
+                        rest = &rest[end + CLOSE.len()..];
+                    },
+                    None => {
+                        call.push_str(rest);
+                        pending = Some((call, start_line));
+                        break;
+                    },
+                }
+            }
+            if let Some(run) = span {
+                match closing_run(rest, run) {
+                    Some(end) => {
+                        out.push_str(&rest[..end]);
+                        rest = &rest[end..];
+                        span = None;
+                    },
+                    None => {
+                        out.push_str(rest);
+                        break;
+                    },
+                }
+            }
+
+            let next = match rest.find(|c| c == '{' || c == '`' || c == '\\') {
+                Some(next) => next,
+                None => {
+                    out.push_str(rest);
+                    break;
+                },
+            };
+            out.push_str(&rest[..next]);
+            rest = &rest[next..];
+
+            if rest.starts_with(OPEN) {
+                pending = Some((String::new(), n + 1));
+                rest = &rest[OPEN.len()..];
+            }
+            else if rest.starts_with('`') {
+                // An unclosed run is just backticks
+                let run = backtick_run(rest);
+                out.push_str(&rest[..run]);
+                rest = &rest[run..];
+                let from = line_start + line.len() - rest.len();
+                if from > para_end {
+                    para_end = from + paragraph_len(&in_buf[from..]);
+                }
+                if !unclosed.contains(&(run, para_end)) {
+                    if closing_run(&in_buf[from..para_end], run).is_some() {
+                        span = Some(run);
+                    }
+                    else {
+                        unclosed.push((run, para_end));
+                    }
+                }
+            }
+            else {
+                // A lone {, or a backslash along with the character it escapes
+                let len = match rest[1..].chars().next() {
+                    Some(c) if rest.starts_with('\\') => 1 + c.len_utf8(),
+                    _ => 1,
+                };
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    if let Some((_, start_line)) = pending {
+        return Err(shortcode_err(source, start_line, "unterminated shortcode".to_owned()));
+    }
+
+    Ok(out)
+}
+
+fn read_file(path: &Path, call: &Shortcode, source: &Path) -> Result<String, QuiltError> {
+    let mut buf = String::new();
+    match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut buf)) {
+        Ok(_)  => Ok(buf),
+        Err(e) => Err(shortcode_err(source, call.line, format!("{} could not read {}: {}",
+                                                               call.name, path.display(), e))),
+    }
+}
+
+// Paths starting with ./ or ../ are relative to the including file, others to the site root.
+// The result is normalised, so one file reached along two routes gives the same path.
+pub fn resolve_path(target: &str, root: &Path, source: &Path) -> PathBuf {
+    if target.starts_with("./") || target.starts_with("../") {
+        normalise(&source.parent().unwrap_or(root).join(target))
+    }
+    else {
+        normalise(&root.join(target))
+    }
+}
+
+// Cuts out `lines="10-30"` or the lines between `ANCHOR: name` and `ANCHOR_END: name` markers
+fn select_lines(code: &str, call: &Shortcode, source: &Path) -> Result<String, QuiltError> {
+    let lines : Vec<&str> = code.lines().collect();
+
+    let selected : Vec<&str> = {
+        if let Some(range) = call.args.get("lines") {
+            let mut ends = range.splitn(2, '-').map(|n| n.trim());
+            let first = ends.next().and_then(|n| n.parse::<usize>().ok());
+            let last  = match ends.next() {
+                Some("") => Some(lines.len()),
+                Some(n)  => n.parse::<usize>().ok(),
+                None     => first,
+            };
+            match (first, last) {
+                (Some(a), Some(b)) if a >= 1 && a <= b && b <= lines.len() => lines[a - 1..b].to_vec(),
+                _ => return Err(shortcode_err(source, call.line,
+                                              format!("code has invalid line range '{}' ({} lines)",
+                                                      range, lines.len()))),
+            }
+        }
+        else if let Some(region) = call.args.get("region") {
+            let start_mark = format!("ANCHOR: {}", region);
+            let end_mark   = format!("ANCHOR_END: {}", region);
+            let start = lines.iter().position(|l| l.trim_right().ends_with(&start_mark));
+            let end   = lines.iter().position(|l| l.trim_right().ends_with(&end_mark));
+            match (start, end) {
+                (Some(a), Some(b)) if a < b => lines[a + 1..b].to_vec(),
+                _ => return Err(shortcode_err(source, call.line,
+                                              format!("code has no region '{}'", region))),
+            }
+        }
+        else {
+            lines
+        }
+    };
+
+    let kept = selected.into_iter().filter(|l| !(l.contains("ANCHOR: ") || l.contains("ANCHOR_END: ")));
+    Ok(kept.collect::<Vec<&str>>().join("\n"))
+}
+
+fn code_block(code: &str, lang: &str) -> String {
+    // The fence must be longer than any run of backticks in the code itself
+    let mut longest = 0;
+    let mut run     = 0;
+    for c in code.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat((longest + 1).max(3));
+    format!("{}{}\n{}\n{}", fence, lang, code, fence)
+}
+
+struct Expander<'a> {
+    shortcode_dir : Option<&'a Path>,
+    root          : &'a Path,
+    templates     : HashMap<String, String>,
+    deps          : Vec<PathBuf>,
+    stack         : Vec<PathBuf>,
+}
+
+impl<'a> Expander<'a> {
+    fn expand(&mut self, in_buf: &str, source: &Path) -> Result<String, QuiltError> {
+        self.stack.push(normalise(source));
+        let out = replace(in_buf, source, |call| self.resolve(call, source));
+        self.stack.pop();
+        out
+    }
+
+    fn depend_on(&mut self, path: &Path) {
+        if !self.deps.iter().any(|d| d == path) {
+            self.deps.push(path.to_path_buf());
+        }
+    }
+
+    fn resolve(&mut self, call: &Shortcode, source: &Path) -> Result<String, QuiltError> {
+        match call.name.as_str() {
+            "include" => {
+                let target = match call.args.get("0") {
+                    Some(target) => resolve_path(target, self.root, source),
+                    None => return Err(shortcode_err(source, call.line, "include needs a file".to_owned())),
+                };
+                if self.stack.contains(&target) {
+                    return Err(shortcode_err(source, call.line,
+                                             format!("{} includes itself", target.display())));
+                }
+                if self.stack.len() >= MAX_INCLUDE_DEPTH {
+                    return Err(shortcode_err(source, call.line,
+                                             format!("includes nested more than {} deep", MAX_INCLUDE_DEPTH)));
+                }
+                let included = read_file(&target, call, source)?;
+                self.depend_on(&target);
+                self.expand(&included, &target)
+            },
+            "code" => {
+                let target = match call.args.get("0") {
+                    Some(target) => resolve_path(target, self.root, source),
+                    None => return Err(shortcode_err(source, call.line, "code needs a file".to_owned())),
+                };
+                let code = read_file(&target, call, source)?;
+                self.depend_on(&target);
+
+                let lang = match call.args.get("lang") {
+                    Some(lang) => lang.to_owned(),
+                    None => target.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_default(),
+                };
+                Ok(code_block(&select_lines(&code, call, source)?, &lang))
+            },
+            _ => {
+                if !self.templates.contains_key(&call.name) {
+                    let temp_path = self.shortcode_dir.map(|dir| dir.join(format!("{}.html", call.name)));
+                    match temp_path {
+                        Some(ref temp_path) if temp_path.exists() => {
+                            let temp_buf = read_file(temp_path, call, source)?;
+                            self.templates.insert(call.name.clone(), temp_buf);
+                        },
+                        _ => return Err(shortcode_err(source, call.line,
+                                                      format!("unknown shortcode '{}'", call.name))),
+                    }
+                }
+                render(&self.templates[&call.name], call, source)
+            },
+        }
+    }
+}
+
+// Expands shortcodes in `in_buf` using the built-in include and code shortcodes and the templates
+// in `shortcode_dir` (themes/<theme>/shortcodes). Returns the files that were pulled in.
+pub fn expand(in_buf: &str, shortcode_dir: Option<&Path>, root: &Path,
+              source: &Path) -> Result<(String, Vec<PathBuf>), QuiltError> {
+    let mut expander = Expander {
+        shortcode_dir : shortcode_dir,
+        root          : root,
+        templates     : HashMap::new(),
+        deps          : vec![],
+        stack         : vec![],
+    };
+
+    let out = expander.expand(in_buf, source)?;
+    Ok((out, expander.deps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::TempSite;
+
+    fn expand_page(site: &TempSite, page: &str) -> Result<String, QuiltError> {
+        let source = site.root.join(page);
+        let mut buf = String::new();
+        fs::File::open(&source).unwrap().read_to_string(&mut buf).unwrap();
+        expand(&buf, None, &site.root, &source).map(|(out, _)| out)
+    }
+
+    fn replace_all(in_buf: &str) -> String {
+        match replace(in_buf, Path::new("page.md"), |call| Ok(format!("[{}]", call.name))) {
+            Ok(out) => out,
+            Err(e)  => panic!("{}", e.message),
+        }
+    }
+
+    #[test]
+    fn code_is_left_alone() {
+        for page in &["Use `{{< youtube id=\"x\" >}}` to embed",
+                      "Use ``{{< a >}} ` {{< b >}}`` here",
+                      "A span `{{< a >}}\nacross lines` here",
+                      "Text\n\n    {{< a >}}\n\n    {{< b >}}\n",
+                      "````\n```\n{{< a >}}\n```\n````",
+                      "~~~\n```\n{{< a >}}\n~~~",
+                      "Escaped \\{{< a >}}"] {
+            assert_eq!(replace_all(page), *page);
+        }
+    }
+
+    #[test]
+    fn shortcodes_outside_code_are_replaced() {
+        assert_eq!(replace_all("An `unclosed {{< a >}}\n\nrun` {{< b >}}"), "An `unclosed [a]\n\nrun` [b]");
+        assert_eq!(replace_all("````\n```\n````\n{{< a >}}"), "````\n```\n````\n[a]");
+        assert_eq!(replace_all("Text\n    {{< a >}}"), "Text\n    [a]");
+        assert_eq!(replace_all("- item\n\n    {{< a >}}"), "- item\n\n    [a]");
+        assert_eq!(replace_all("`code` {{< a\n  x=\"1\" >}} `more`"), "`code` [a] `more`");
+    }
+
+    #[test]
+    fn include_cycle_through_parent_dir_is_an_error() {
+        let site = TempSite::new("include-self", &[
+            ("site/docs/a.md", "{{< include \"../docs/a.md\" >}}"),
+            ("site/docs/b.md", "{{< include \"./c.md\" >}}"),
+            ("site/docs/c.md", "{{< include \"../docs/./b.md\" >}}"),
+        ]);
+        for page in &["site/docs/a.md", "site/docs/b.md"] {
+            match expand_page(&site, page) {
+                Err(e) => assert!(e.message.contains("includes itself"), "{}", e.message),
+                Ok(_)  => panic!("{} expanded", page),
+            }
+        }
+    }
+
+    #[test]
+    fn include_reached_along_two_routes_is_one_dependency() {
+        let site = TempSite::new("include-twice", &[
+            ("site/docs/part.md", "part"),
+        ]);
+        let source = site.root.join("site/docs/a.md");
+        match expand("{{< include \"./part.md\" >}} {{< include \"../docs/part.md\" >}}", None, &site.root, &source) {
+            Ok((out, deps)) => {
+                assert_eq!(out, "part part");
+                assert_eq!(deps, vec![site.root.join("site/docs/part.md")]);
+            },
+            Err(e) => panic!("{}", e.message),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use pulldown_cmark::{Event, Tag};
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PageStats {
+    pub word_count   : usize,
+    pub reading_time : usize,
+}
+
+// Han, kana and hangul are written without spaces, so each character counts as a word
+fn is_cjk(c: char) -> bool {
+    match c as u32 {
+        0x3040 ... 0x30FF   |
+        0x3400 ... 0x4DBF   |
+        0x4E00 ... 0x9FFF   |
+        0xAC00 ... 0xD7AF   |
+        0xF900 ... 0xFAFF   |
+        0x20000 ... 0x2FA1F => true,
+        _                   => false,
+    }
+}
+
+pub fn count_words(text: &str) -> usize {
+    let mut count   = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_cjk(c) {
+            count  += 1;
+            in_word = false;
+        }
+        else if c.is_whitespace() {
+            in_word = false;
+        }
+        else if !in_word {
+            count  += 1;
+            in_word = true;
+        }
+    }
+    count
+}
+
+// Counts the words of the rendered text, leaving out code blocks
+pub fn measure<'a>(events: &[Event<'a>], words_per_minute: usize) -> PageStats {
+    let mut word_count = 0;
+    let mut in_code    = false;
+
+    for event in events {
+        match *event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_))   => in_code = false,
+            Event::Text(ref text) if !in_code => word_count += count_words(text),
+            _ => (),
+        }
+    }
+
+    let wpm = if words_per_minute == 0 { 1 } else { words_per_minute };
+    PageStats {
+        word_count   : word_count,
+        reading_time : (word_count + wpm - 1) / wpm,
+    }
+}
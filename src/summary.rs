@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use pulldown_cmark as markdown;
+use pulldown_cmark::{Event, Tag};
+
+use super::{QuiltError, Config, ConfigSanitize, Site, escape_html, shortcode_dir, bibliography_path};
+use citations::{Bibliography, Style};
+use extensions::{self, MarkdownOptions};
+use formats::Registry;
+use shortcodes;
+use links;
+use sanitize;
+
+const MORE_MARKER: &'static str = "<!-- more -->";
+
+#[derive(Debug)]
+pub struct Summary {
+    pub html : String,
+    pub text : String,
+}
+
+// Byte offset just past the nth whitespace-separated word of `text`
+fn word_end(text: &str, n: usize) -> usize {
+    if n == 0 {
+        return 0;
+    }
+    let mut seen    = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if in_word {
+                seen += 1;
+                if seen == n {
+                    return i;
+                }
+            }
+            in_word = false;
+        }
+        else {
+            in_word = true;
+        }
+    }
+    text.len()
+}
+
+// Keeps the first `words` words of the document, closing any tags left open
+fn truncate<'a, I: Iterator<Item=Event<'a>>>(events: I, words: usize) -> Vec<Event<'a>> {
+    let mut kept  : Vec<Event<'a>> = vec![];
+    let mut open  : Vec<Tag<'a>>   = vec![];
+    let mut count = 0;
+
+    for event in events {
+        match event {
+            Event::Start(tag) => {
+                open.push(tag.clone());
+                kept.push(Event::Start(tag));
+            },
+            Event::End(tag) => {
+                open.pop();
+                kept.push(Event::End(tag));
+            },
+            Event::Text(text) => {
+                let n = text.split_whitespace().count();
+                if count + n >= words {
+                    let mut cut = text[..word_end(&text, words - count)].to_owned();
+                    if count + n > words {
+                        cut.push_str("…");
+                    }
+                    kept.push(Event::Text(cut.into()));
+                    while let Some(tag) = open.pop() {
+                        kept.push(Event::End(tag));
+                    }
+                    break;
+                }
+                count += n;
+                kept.push(Event::Text(text));
+            },
+            other => kept.push(other),
+        }
+    }
+
+    kept
+}
+
+// Where a <!-- more --> is, found in the document's HTML so one shown inside code doesn't count
+fn more_marker<'a>(events: &[Event<'a>]) -> Option<usize> {
+    events.iter().position(|event| {
+        match *event {
+            Event::Html(ref html) | Event::InlineHtml(ref html) => html.contains(MORE_MARKER),
+            _ => false,
+        }
+    })
+}
+
+// Closes the tags left open by cutting the document short
+fn close_tags<'a>(mut events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut open : Vec<Tag<'a>> = vec![];
+    for event in &events {
+        match *event {
+            Event::Start(ref tag) => open.push(tag.clone()),
+            Event::End(_)         => { open.pop(); },
+            _ => (),
+        }
+    }
+    while let Some(tag) = open.pop() {
+        events.push(Event::End(tag));
+    }
+    events
+}
+
+fn plain_text<'a>(events: &[Event<'a>]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match *event {
+            Event::Text(ref t) => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Header(_)) | Event::End(Tag::Item) => text.push(' '),
+            _ => (),
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// The passes a page body goes through which also apply to its summary
+pub struct Rewrite<'s> {
+    pub page_path    : &'s Path,
+    pub md_path      : &'s Path,
+    pub site         : &'s Site,
+    pub bibliography : Option<&'s Bibliography>,
+    pub style        : Style,
+    pub sanitize     : Option<&'s ConfigSanitize>,
+}
+
+// Prefers an explicit `summary`, then everything before <!-- more -->, then the first words
+pub fn summarise(in_buf: &str, explicit: Option<&str>, words: usize, opts: &MarkdownOptions,
+                 rewrite: &Rewrite) -> Result<Summary, QuiltError> {
+    let explicit = explicit.map(|summary| extensions::preprocess(summary.to_owned(), opts));
+    let events : Vec<Event> = {
+        if let Some(ref summary) = explicit {
+            extensions::parse(summary, opts)
+        }
+        else {
+            let events = extensions::parse(in_buf, opts);
+            match more_marker(&events) {
+                Some(more) => close_tags(events.into_iter().take(more).collect()),
+                None       => truncate(events.into_iter(), words),
+            }
+        }
+    };
+    let site   = rewrite.site;
+    let events = links::rewrite(events, rewrite.page_path, rewrite.md_path, &site.urls, &site.assets)?;
+    let events = site.wiki.rewrite(events, rewrite.page_path, rewrite.md_path, &site.urls)?;
+    let events = match rewrite.bibliography {
+        Some(bib) => bib.cite(events, rewrite.style, rewrite.md_path)?.0,
+        None      => events,
+    };
+
+    let mut html = String::new();
+    let text = plain_text(&events);
+    markdown::html::push_html(&mut html, events.into_iter());
+    if let Some(policy) = rewrite.sanitize {
+        html = sanitize::sanitize(&html, policy);
+    }
+
+    Ok(Summary {html: html, text: escape_html(&text)})
+}
+
+pub fn collect(config: &Config, formats: &Registry, opts: &MarkdownOptions, site_root: &Path,
+               site: &Site) -> Result<HashMap<PathBuf, Summary>, QuiltError> {
+    let mut summaries = HashMap::new();
+
+    for (path, page) in &site.pages {
+        if !page.has_content() {
+            continue;
+        }
+
+        let explicit = page.page_toml.summary.as_ref().map(|s| s.as_str());
+        let opts     = opts.merge(&page.page_toml.markdown);
+        let md_path  = site.content_path(path, page);
+        let style    = page.page_toml.citation_style.as_ref().unwrap_or(&config.bibliography.style);
+        let rewrite  = Rewrite {
+            page_path    : path,
+            md_path      : &md_path,
+            site         : site,
+            bibliography : bibliography_path(&config.bibliography, site_root, page, &md_path)
+                               .map(|p| &site.bibliographies[&p]),
+            style        : Style::parse(style)?,
+            sanitize     : config.sanitize_policy(path),
+        };
+
+        let md_buf = match formats.of(page).and_then(|f| f.markdown_source(&site.sources[path])) {
+            Some(md_buf) => md_buf,
+            None => {
+                let summary = summarise("", explicit, config.summary.words, &opts, &rewrite)?;
+                summaries.insert(path.clone(), summary);
+                continue;
+            },
+        };
+
+        let shortcode_dir = shortcode_dir(&site.themes_dir, &page.page_toml);
+        let (md_buf, _) = shortcodes::expand(&md_buf, shortcode_dir.as_ref().map(|d| d.as_path()),
+                                             site_root, &md_path)?;
+
+        let md_buf   = extensions::preprocess(md_buf, &opts);
+        let summary  = summarise(&md_buf, explicit, config.summary.words, &opts, &rewrite)?;
+        summaries.insert(path.clone(), summary);
+    }
+
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn before_more(in_buf: &str) -> Option<String> {
+        let events = extensions::parse(in_buf, &MarkdownOptions::default());
+        more_marker(&events).map(|more| {
+            let mut html = String::new();
+            markdown::html::push_html(&mut html, close_tags(events.into_iter().take(more).collect()).into_iter());
+            html
+        })
+    }
+
+    #[test]
+    fn more_marker_ends_the_summary() {
+        assert_eq!(before_more("Intro\n\n<!-- more -->\n\nRest"), Some("<p>Intro</p>\n".to_owned()));
+        assert_eq!(before_more("*Intro <!-- more --> rest*"), Some("<p><em>Intro </em></p>\n".to_owned()));
+    }
+
+    #[test]
+    fn more_marker_in_code_is_ignored() {
+        assert_eq!(before_more("Intro\n\n```html\n<!-- more -->\n```\n\nRest"), None);
+        assert_eq!(before_more("Write `<!-- more -->` to cut"), None);
+        assert_eq!(before_more("Intro\n\n    <!-- more -->\n\nRest"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use pulldown_cmark::{Event, Tag};
+
+use super::escape_html;
+
+#[derive(Debug)]
+pub struct TocEntry {
+    pub level : i32,
+    pub title : String,
+    pub slug  : String,
+}
+
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        }
+        else if (c.is_whitespace() || c == '-' || c == '_') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    if slug.is_empty() {
+        slug.push_str("section");
+    }
+    slug
+}
+
+// Repeated slugs gain a -1, -2, ... suffix in document order
+fn unique_slug(base: String, used: &mut HashSet<String>) -> String {
+    let mut slug = base.clone();
+    let mut n    = 1;
+    while used.contains(&slug) {
+        slug = format!("{}-{}", base, n);
+        n += 1;
+    }
+    used.insert(slug.clone());
+    slug
+}
+
+// Gives every heading an id (and, with `anchors`, a ¶ link to itself), collecting the table of contents
+pub fn anchor_headings<'a>(events: Vec<Event<'a>>, anchors: bool) -> (Vec<Event<'a>>, Vec<TocEntry>) {
+    let mut out : Vec<Event<'a>> = Vec::with_capacity(events.len());
+    let mut toc : Vec<TocEntry>  = vec![];
+    let mut used  = HashSet::new();
+    let mut title = String::new();
+    let mut start : Option<usize> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Header(_)) => {
+                title.clear();
+                start = Some(out.len());
+                out.push(event);
+            },
+            Event::End(Tag::Header(level)) => {
+                let slug = unique_slug(slugify(&title), &mut used);
+                if let Some(idx) = start.take() {
+                    out[idx] = Event::Html(Cow::Owned(format!("<h{} id=\"{}\">", level, slug)));
+                }
+
+                let anchor = if anchors {
+                    format!(" <a class=\"anchor\" href=\"#{}\">¶</a>", slug)
+                } else {
+                    String::new()
+                };
+                out.push(Event::Html(Cow::Owned(format!("{}</h{}>\n", anchor, level))));
+
+                toc.push(TocEntry {level: level, title: title.trim().to_owned(), slug: slug});
+            },
+            Event::Text(ref text) if start.is_some() => {
+                title.push_str(text);
+                out.push(Event::Text(text.clone()));
+            },
+            other => out.push(other),
+        }
+    }
+
+    (out, toc)
+}
+
+pub fn render(toc: &[TocEntry]) -> String {
+    if toc.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::from("<nav class=\"toc\">\n");
+    let mut levels : Vec<i32> = vec![];
+
+    for entry in toc {
+        match levels.last().cloned() {
+            None => {
+                out.push_str("<ul>\n");
+                levels.push(entry.level);
+            },
+            Some(top) if entry.level > top => {
+                out.push_str("\n<ul>\n");
+                levels.push(entry.level);
+            },
+            Some(_) => {
+                // Close lists until the entry is deeper than the one it would be nested in, then
+                // take the place of the last entry at this depth (h2, h4, h3 makes the h3 a sibling of the h4)
+                out.push_str("</li>\n");
+                while levels.len() > 1 && entry.level <= levels[levels.len() - 2] {
+                    levels.pop();
+                    out.push_str("</ul>\n</li>\n");
+                }
+                *levels.last_mut().unwrap() = entry.level;
+            },
+        }
+        out.push_str(&format!("<li><a href=\"#{}\">{}</a>", entry.slug, escape_html(&entry.title)));
+    }
+
+    for _ in levels {
+        out.push_str("</li>\n</ul>\n");
+    }
+    out.push_str("</nav>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(levels: &[i32]) -> Vec<TocEntry> {
+        levels.iter().enumerate().map(|(i, &level)| {
+            TocEntry {level: level, title: format!("h{}", i), slug: format!("h{}", i)}
+        }).collect()
+    }
+
+    #[test]
+    fn skipped_levels_nest_one_step() {
+        let html = render(&entries(&[2, 4, 2]));
+        assert_eq!(html, "<nav class=\"toc\">\n<ul>\n<li><a href=\"#h0\">h0</a>\n<ul>\n<li><a href=\"#h1\">h1</a>\
+                          </li>\n</ul>\n</li>\n<li><a href=\"#h2\">h2</a></li>\n</ul>\n</nav>");
+    }
+
+    #[test]
+    fn lists_are_balanced() {
+        for levels in &[vec![2, 4, 2], vec![2, 4, 3, 4, 2], vec![4, 2, 3], vec![1, 3, 5, 2, 6, 1]] {
+            let html = render(&entries(levels));
+            assert_eq!(html.matches("<ul>").count(), html.matches("</ul>").count(), "{:?}", levels);
+            assert_eq!(html.matches("<li>").count(), html.matches("</li>").count(), "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn shallower_heading_after_skip_is_a_sibling() {
+        let html = render(&entries(&[2, 4, 3]));
+        assert_eq!(html.matches("<ul>").count(), 2);
+        assert!(html.contains("<li><a href=\"#h1\">h1</a></li>\n<li><a href=\"#h2\">h2</a>"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use pulldown_cmark::{Event, Tag};
+
+use super::{QuiltError, Site, escape_html};
+use extensions::{self, MarkdownOptions};
+use formats::Registry;
+use toc::slugify;
+
+#[derive(Debug, Default)]
+pub struct WikiIndex {
+    titles    : HashMap<PathBuf, String>,
+    // Slugified page names and titles, for matching [[Page Name]]
+    slugs     : Vec<(String, PathBuf)>,
+    backlinks : HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+struct WikiLink<'t> {
+    target   : &'t str,
+    fragment : Option<&'t str>,
+    label    : Option<&'t str>,
+}
+
+enum Part<'t> {
+    Text(&'t str),
+    Link(WikiLink<'t>),
+}
+
+// Splits text around [[target#fragment|label]] links
+fn split_links(text: &str) -> Vec<Part> {
+    let mut parts = vec![];
+    let mut rest  = text;
+
+    while let Some(start) = rest.find("[[") {
+        let end = match rest[start..].find("]]") {
+            Some(end) => start + end,
+            None      => break,
+        };
+        if start > 0 {
+            parts.push(Part::Text(&rest[..start]));
+        }
+
+        let inner = &rest[start + 2..end];
+        let mut split = inner.splitn(2, '|');
+        let dest  = split.next().unwrap().trim();
+        let label = split.next().map(|l| l.trim());
+        let mut dest_split = dest.splitn(2, '#');
+
+        parts.push(Part::Link(WikiLink {target  : dest_split.next().unwrap().trim(),
+                                        fragment: dest_split.next(),
+                                        label   : label}));
+        rest = &rest[end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    parts
+}
+
+fn wiki_err(md_path: &Path, message: String) -> QuiltError {
+    QuiltError {source : "Wiki".to_owned(),
+                message: format!("{}: {}", md_path.display(), message)}
+}
+
+impl WikiIndex {
+    // Indexes page names and titles, then finds every page's wiki links to collect backlinks
+    pub fn build(site: &Site, formats: &Registry, opts: &MarkdownOptions) -> Result<WikiIndex, QuiltError> {
+        let mut index = WikiIndex::default();
+
+        let mut keys : Vec<&PathBuf> = site.pages.iter().filter(|&(_, p)| p.has_content()).map(|(k, _)| k).collect();
+        keys.sort();
+
+        for key in &keys {
+            let page  = &site.pages[*key];
+            let title = page.page_toml.title.clone().unwrap_or_else(|| page.name.clone());
+            index.slugs.push((slugify(&page.name), (*key).clone()));
+            if page.page_toml.title.is_some() {
+                index.slugs.push((slugify(&title), (*key).clone()));
+            }
+            index.titles.insert((*key).clone(), title);
+        }
+
+        // Only pages with markdown are searched for links, though any page can be linked to
+        for key in &keys {
+            let page   = &site.pages[*key];
+            let md_buf = match formats.of(page).and_then(|f| f.markdown_source(&site.sources[*key])) {
+                Some(md_buf) => md_buf,
+                None         => continue,
+            };
+            let md_path = site.content_path(key, page);
+
+            let mut in_code = false;
+            for event in extensions::parse(&md_buf, opts) {
+                match event {
+                    Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => in_code = true,
+                    Event::End(Tag::CodeBlock(_))   | Event::End(Tag::Code)   => in_code = false,
+                    Event::Text(ref text) if !in_code => {
+                        for part in split_links(text) {
+                            if let Part::Link(link) = part {
+                                let target = index.resolve(link.target, key, &md_path)?;
+                                if target == **key {
+                                    continue;
+                                }
+                                let from = index.backlinks.entry(target).or_insert_with(Vec::new);
+                                if !from.contains(*key) {
+                                    from.push((*key).clone());
+                                }
+                            }
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    // `section/page` names a page by path; anything else is matched against page names and titles,
+    // preferring pages in the linking page's own section
+    fn resolve(&self, target: &str, from: &Path, md_path: &Path) -> Result<PathBuf, QuiltError> {
+        if target.contains('/') {
+            let mut key = Path::new("site").join(target.trim_matches('/'));
+            key.set_extension("");
+            if self.titles.contains_key(&key) {
+                return Ok(key);
+            }
+            return Err(wiki_err(md_path, format!("[[{}]] does not name a page", target)));
+        }
+
+        let slug = slugify(target);
+        let mut matches : Vec<&PathBuf> = self.slugs.iter().filter(|&&(ref s, _)| *s == slug).map(|&(_, ref k)| k).collect();
+        matches.dedup();
+
+        if matches.len() > 1 {
+            let local : Vec<&PathBuf> = matches.iter().cloned().filter(|k| k.parent() == from.parent()).collect();
+            if local.len() == 1 {
+                matches = local;
+            }
+        }
+
+        match matches.len() {
+            0 => Err(wiki_err(md_path, format!("[[{}]] does not match any page", target))),
+            1 => Ok(matches[0].clone()),
+            _ => Err(wiki_err(md_path, format!("[[{}]] is ambiguous: {}", target,
+                                               matches.iter().map(|k| k.display().to_string())
+                                                             .collect::<Vec<String>>().join(", ")))),
+        }
+    }
+
+    // Replaces [[...]] in text (outside code) with links to the pages they name
+    pub fn rewrite<'a>(&self, events: Vec<Event<'a>>, page_path: &Path, md_path: &Path,
+                       urls: &HashMap<PathBuf, String>) -> Result<Vec<Event<'a>>, QuiltError> {
+        let mut out = Vec::with_capacity(events.len());
+        let mut in_code = false;
+
+        for event in events {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => {
+                    in_code = true;
+                    out.push(event);
+                },
+                Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => {
+                    in_code = false;
+                    out.push(event);
+                },
+                Event::Text(ref text) if !in_code && text.contains("[[") => {
+                    for part in split_links(text) {
+                        match part {
+                            Part::Text(plain) => out.push(Event::Text(Cow::Owned(plain.to_owned()))),
+                            Part::Link(link) => {
+                                let key = self.resolve(link.target, page_path, md_path)?;
+                                let mut url = urls[&key].clone();
+                                if let Some(fragment) = link.fragment {
+                                    url.push('#');
+                                    url.push_str(fragment);
+                                }
+
+                                let label = match link.label {
+                                    Some(label)                          => label.to_owned(),
+                                    None if link.target.contains('/')    => self.titles[&key].clone(),
+                                    None                                 => link.target.to_owned(),
+                                };
+
+                                out.push(Event::Start(Tag::Link(Cow::Owned(url.clone()), Cow::Borrowed(""))));
+                                out.push(Event::Text(Cow::Owned(label)));
+                                out.push(Event::End(Tag::Link(Cow::Owned(url), Cow::Borrowed(""))));
+                            },
+                        }
+                    }
+                },
+                other => out.push(other),
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn render_backlinks(&self, page_path: &Path, urls: &HashMap<PathBuf, String>) -> String {
+        let from = match self.backlinks.get(page_path) {
+            Some(from) if !from.is_empty() => from,
+            _ => return String::new(),
+        };
+
+        let mut links : Vec<(&String, &String)> = from.iter().map(|k| (&self.titles[k], &urls[k])).collect();
+        links.sort();
+
+        let mut out = String::from("<ul class=\"backlinks\">\n");
+        for (title, url) in links {
+            out.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", escape_html(url), escape_html(title)));
+        }
+        out.push_str("</ul>");
+        out
+    }
+}